@@ -32,13 +32,16 @@ impl Policy for ClockPro {
 }
 
 impl ClockPro {
-    pub fn new(size: usize, metadata: &mut MetaData) -> Self {
+    /// Create policy, mem_cold adaptation is clamped to [min_cold, max_cold]
+    pub fn new(size: usize, min_cold: usize, max_cold: usize, metadata: &mut MetaData) -> Self {
         let link = Link::new(1, size as u32 * 2, metadata);
+        let mem_cold_max = max_cold.min(size);
+        let mem_cold_min = min_cold.max(1).min(mem_cold_max);
         Self {
             mem_max: size,
-            mem_cold: size / 2,
-            mem_cold_min: 1,
-            mem_cold_max: 3 * size / 4,
+            mem_cold: (size / 2).clamp(mem_cold_min, mem_cold_max),
+            mem_cold_min,
+            mem_cold_max,
             hand_hot: link.root,
             hand_cold: link.root,
             hand_test: link.root,
@@ -255,6 +258,111 @@ impl ClockPro {
     pub fn len(&self) -> usize {
         self.count_cold + self.count_hot
     }
+
+    /// Drop all pages, counters and hands are reset as in new
+    pub fn clear(&mut self, metadata: &mut MetaData) {
+        self.link.clear(metadata);
+        self.hand_hot = self.link.root;
+        self.hand_cold = self.link.root;
+        self.hand_test = self.link.root;
+        self.count_hot = 0;
+        self.count_cold = 0;
+        self.count_test = 0;
+        self.mem_cold = (self.mem_max / 2).clamp(self.mem_cold_min, self.mem_cold_max);
+    }
+
+    /// Keys grouped as (hot, cold, test) pages, each in clock order
+    pub fn pages(&self, metadata: &MetaData) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut pages = (Vec::new(), Vec::new(), Vec::new());
+        for index in self.link.iter(metadata) {
+            let entry = &metadata.data[index as usize];
+            let key = entry.key.to_string();
+            match entry.clock_info.1 {
+                HOT_PAGE => pages.0.push(key),
+                COLD_PAGE => pages.1.push(key),
+                TEST_PAGE => pages.2.push(key),
+                _ => unreachable!(),
+            }
+        }
+        pages
+    }
+
+    /// Keys in clock order, test pages included
+    pub fn dump_order(&self, metadata: &MetaData) -> Vec<String> {
+        self.link.keys(metadata)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.mem_max
+    }
+
+    /// Indices in clock order, (hot, cold, test) hands and mem_cold. A hand is None
+    /// when it points at list head.
+    pub fn state(&self, metadata: &MetaData) -> (Vec<u32>, [Option<u32>; 3], usize) {
+        let hand = |index: u32| (index != self.link.root).then_some(index);
+        (
+            self.link.iter(metadata).collect(),
+            [
+                hand(self.hand_hot),
+                hand(self.hand_cold),
+                hand(self.hand_test),
+            ],
+            self.mem_cold,
+        )
+    }
+
+    /// Rebuild an empty policy from state, pages are read from entry clock_info.
+    /// Hands must be in order or None, mem_cold is clamped to current bounds.
+    pub fn restore(
+        &mut self,
+        order: &[u32],
+        hands: [Option<u32>; 3],
+        mem_cold: usize,
+        metadata: &mut MetaData,
+    ) {
+        for &index in order {
+            match metadata.data[index as usize].clock_info.1 {
+                COLD_PAGE => self.count_cold += 1,
+                HOT_PAGE => self.count_hot += 1,
+                TEST_PAGE => self.count_test += 1,
+                _ => unreachable!(),
+            }
+            self.link.insert_before(index, self.link.root, metadata);
+        }
+        let root = self.link.root;
+        self.hand_hot = hands[0].unwrap_or(root);
+        self.hand_cold = hands[1].unwrap_or(root);
+        self.hand_test = hands[2].unwrap_or(root);
+        self.mem_cold = mem_cold.clamp(self.mem_cold_min, self.mem_cold_max);
+    }
+
+    /// Update capacity, mem_cold and its bounds are scaled with it. Run cold/test hands until
+    /// policy fits, return demoted test pages and removed test pages.
+    pub fn set_capacity(&mut self, size: usize, metadata: &mut MetaData) -> (Vec<u32>, Vec<u32>) {
+        let scale = |value: usize| {
+            if self.mem_max == 0 {
+                return value;
+            }
+            value * size / self.mem_max
+        };
+        self.mem_cold_max = scale(self.mem_cold_max).min(size);
+        self.mem_cold_min = scale(self.mem_cold_min).max(1).min(self.mem_cold_max);
+        self.mem_cold = scale(self.mem_cold).clamp(self.mem_cold_min, self.mem_cold_max);
+        self.mem_max = size;
+        self.link.capacity = size as u32 * 2;
+
+        let mut tests = Vec::new();
+        let mut removed = Vec::new();
+        while self.count_test > self.mem_max {
+            removed.extend(self._hand_test(metadata));
+        }
+        while self.count_hot + self.count_cold > self.mem_max {
+            let (test, test_removed) = self._hand_cold(metadata);
+            tests.extend(test);
+            removed.extend(test_removed);
+        }
+        (tests, removed)
+    }
 }
 
 #[cfg(test)]
@@ -274,7 +382,7 @@ mod tests {
     fn test_clock_pro_simple() {
         let mut metadata = MetaData::new(5);
         let clock = Clock::new();
-        let mut policy = ClockPro::new(5, &mut metadata);
+        let mut policy = ClockPro::new(5, 1, 3, &mut metadata);
 
         for i in 0..5 {
             let (test, removed) = policy.set(
@@ -303,6 +411,12 @@ mod tests {
         assert_eq!(policy.count_hot, 0);
         assert_eq!(policy.count_test, 5);
         assert!(key_to_index("key:9", &mut metadata) < 12);
+        let (hot, mut cold, mut test) = policy.pages(&metadata);
+        cold.sort();
+        test.sort();
+        assert!(hot.is_empty());
+        assert_eq!(cold, ["key:5", "key:6", "key:7", "key:8", "key:9"]);
+        assert_eq!(test, ["key:0", "key:1", "key:2", "key:3", "key:4"]);
 
         // set key 1 again, test page -> hot page
         let index = policy.access("key:1", &clock, &mut metadata);
@@ -358,4 +472,37 @@ mod tests {
         assert_eq!(policy.count_hot, 3);
         assert_eq!(policy.count_test, 5);
     }
+
+    #[test]
+    fn test_clock_pro_cold_bounds() {
+        let mut metadata = MetaData::new(100);
+        let mut policy = ClockPro::new(100, 20, 60, &mut metadata);
+        assert_eq!(policy.mem_cold, 50);
+        let (mut lowest, mut highest) = (policy.mem_cold, policy.mem_cold);
+
+        // looping over 2x capacity hits test pages, long scans expire them
+        for round in 0..20 {
+            for i in 0..400 {
+                let key = format!("loop:{}:{}", round, i % 200);
+                let (_, removed) = policy.set(key_to_index(&key, &mut metadata), &mut metadata);
+                if let Some(index) = removed {
+                    metadata.remove(index);
+                }
+                assert!(policy.mem_cold >= 20 && policy.mem_cold <= 60);
+                lowest = lowest.min(policy.mem_cold);
+                highest = highest.max(policy.mem_cold);
+            }
+            for i in 0..1000 {
+                let key = format!("scan:{}:{}", round, i);
+                let (_, removed) = policy.set(key_to_index(&key, &mut metadata), &mut metadata);
+                if let Some(index) = removed {
+                    metadata.remove(index);
+                }
+                assert!(policy.mem_cold >= 20 && policy.mem_cold <= 60);
+                lowest = lowest.min(policy.mem_cold);
+                highest = highest.max(policy.mem_cold);
+            }
+        }
+        assert_eq!((lowest, highest), (20, 60));
+    }
 }
@@ -1,26 +1,34 @@
 use crate::{
     clockpro::ClockPro,
+    filter::BloomFilter,
+    lfu::Lfu,
     lru::Lru,
-    metadata::MetaData,
+    metadata::{MetaData, COLD_PAGE, HOT_PAGE, TEST_PAGE},
     policy::Policy,
+    sketch::CountMinSketch,
     timerwheel::{Cache, TimerWheel},
-    tlfu::TinyLfu,
+    tlfu::{AdmitRecord, TinyLfu},
 };
+use ahash::AHashMap;
 use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
     prelude::*,
     types::{PyDict, PyList},
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 struct PyCache<'a> {
     list: &'a PyList,
     kh: &'a PyDict,
     hk: &'a PyDict,
     sentinel: &'a PyAny,
+    expired: Vec<(u32, String)>,
 }
 
 impl<'a> Cache for PyCache<'a> {
     fn del_item(&mut self, key: &str, index: u32) {
         let _ = self.list.set_item(index as usize, self.sentinel);
+        self.expired.push((index, key.to_string()));
         if let Some(nkey) = key.strip_prefix("_auto:") {
             let num: u64 = nkey.parse().unwrap();
             if let Some(keyh) = self.kh.get_item(num) {
@@ -31,11 +39,209 @@ impl<'a> Cache for PyCache<'a> {
     }
 }
 
+// combined key for namespaced methods, namespace is length prefixed
+// so ("a", "bc") and ("ab", "c") are different keys. The leading NUL keeps
+// them apart from plain keys such as "1:abc", plain keys starting with NUL
+// are reserved.
+fn ns_key(namespace: &str, key: &str) -> String {
+    format!("\0{}:{}{}", namespace.len(), namespace, key)
+}
+
+// op codes of recorded trace events
+const TRACE_SET: u8 = 1;
+const TRACE_ACCESS: u8 = 2;
+const TRACE_REMOVE: u8 = 3;
+
+// called with (key, reason) when an entry leaves cache, reason is "evicted" or "expired"
+type EvictionListener = Box<dyn FnMut(&str, &str) + Send>;
+
+/// TlfuCore.get_or_compute_flag output: (index, should_compute, evicted_index, evicted_key)
+pub type ComputeFlag = (Option<u32>, bool, Option<u32>, Option<String>);
+
+/// ClockProCore.dump output: (key, ttl, referenced, page) in clock order,
+/// (hot, cold, test) hand keys and mem_cold
+pub type ClockProState = (
+    Vec<(String, u64, bool, u8)>,
+    (Option<String>, Option<String>, Option<String>),
+    usize,
+);
+
+// used when values are stored outside of Python list, only collect expired keys
+struct KeyCache {
+    expired: Vec<String>,
+}
+
+impl Cache for KeyCache {
+    fn del_item(&mut self, key: &str, _index: u32) {
+        self.expired.push(key.to_string());
+    }
+}
+
 #[pyclass]
 pub struct TlfuCore {
     pub policy: TinyLfu,
     pub wheel: TimerWheel,
     pub metadata: MetaData,
+    last_activity: u128,
+    last_eviction: Option<(String, String)>,
+    last_migrated: Option<String>,
+    early_expire_beta: f64,
+    hits: u64,
+    misses: u64,
+    // new entries created by set, for churn metrics
+    total_inserts: u64,
+    // moving average of hit ratio updated on each access, None before first access
+    ewma_hit_ratio: Option<f64>,
+    ewma_alpha: f64,
+    eviction_callback: Option<EvictionListener>,
+    // expired entries removed by access, if reap on access is enabled
+    reaped: Option<Vec<(u32, String)>>,
+    // ring of last (key, op) events if tracing is enabled, key strings are reused,
+    // with next write position and number of recorded events
+    trace: Option<Vec<(String, u8)>>,
+    trace_head: usize,
+    trace_len: usize,
+    // set with ttl 0 stores nothing instead of a never expiring entry
+    zero_ttl_no_cache: bool,
+    // count exact hits per entry
+    count_accesses: bool,
+    // separate from policy rng, so shadow sampling doesn't shift admission decisions
+    shadow_rng: StdRng,
+}
+
+/// Point in time view of TlfuCore for monitoring
+#[pyclass]
+pub struct TlfuSnapshot {
+    #[pyo3(get)]
+    pub len: usize,
+    #[pyo3(get)]
+    pub hits: u64,
+    #[pyo3(get)]
+    pub misses: u64,
+    #[pyo3(get)]
+    pub window_len: usize,
+    #[pyo3(get)]
+    pub probation_len: usize,
+    #[pyo3(get)]
+    pub protected_len: usize,
+    #[pyo3(get)]
+    pub scheduled_count: usize,
+    /// window size is fixed, hill climbing adjusts this admission bonus instead
+    #[pyo3(get)]
+    pub lru_factor: usize,
+}
+
+/// Options of TlfuCore, see TlfuCore.__init__ for what each one does. Unset options
+/// keep the defaults of TlfuCore::new.
+#[derive(Clone, Debug)]
+pub struct TlfuConfig {
+    size: usize,
+    admit_ties: bool,
+    early_expire_beta: f64,
+    seed: Option<u64>,
+    no_window: bool,
+    sketch_size: Option<usize>,
+    record_misses: bool,
+    zero_ttl_means_no_cache: bool,
+    recency_only: bool,
+    count_accesses: bool,
+}
+
+impl TlfuConfig {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            admit_ties: false,
+            early_expire_beta: 0.0,
+            seed: None,
+            no_window: false,
+            sketch_size: None,
+            record_misses: true,
+            zero_ttl_means_no_cache: false,
+            recency_only: false,
+            count_accesses: false,
+        }
+    }
+
+    pub fn admit_ties(mut self, enabled: bool) -> Self {
+        self.admit_ties = enabled;
+        self
+    }
+
+    pub fn early_expire_beta(mut self, beta: f64) -> Self {
+        self.early_expire_beta = beta;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn no_window(mut self, enabled: bool) -> Self {
+        self.no_window = enabled;
+        self
+    }
+
+    pub fn sketch_size(mut self, size: usize) -> Self {
+        self.sketch_size = Some(size);
+        self
+    }
+
+    pub fn record_misses(mut self, enabled: bool) -> Self {
+        self.record_misses = enabled;
+        self
+    }
+
+    pub fn zero_ttl_means_no_cache(mut self, enabled: bool) -> Self {
+        self.zero_ttl_means_no_cache = enabled;
+        self
+    }
+
+    pub fn recency_only(mut self, enabled: bool) -> Self {
+        self.recency_only = enabled;
+        self
+    }
+
+    pub fn count_accesses(mut self, enabled: bool) -> Self {
+        self.count_accesses = enabled;
+        self
+    }
+
+    pub fn build(self) -> TlfuCore {
+        let mut core = TlfuCore::new(self.size);
+        core.count_accesses = self.count_accesses;
+        core.policy.recency_only = self.recency_only;
+        core.zero_ttl_no_cache = self.zero_ttl_means_no_cache;
+        core.policy.record_misses = self.record_misses;
+        if let Some(sketch_size) = self.sketch_size {
+            core.policy.sketch = CountMinSketch::new(sketch_size);
+        }
+        if self.no_window {
+            core.policy.disable_window();
+        }
+        core.policy.admit_ties = self.admit_ties;
+        core.early_expire_beta = self.early_expire_beta;
+        if let Some(seed) = self.seed {
+            core.policy.rng = StdRng::seed_from_u64(seed);
+            core.shadow_rng = StdRng::seed_from_u64(seed);
+        }
+        core
+    }
+}
+
+/// Result of ClockProCore.set. Value of demoted_index should be dropped from Python
+/// list only, key stays as a test page. Removed entry is gone entirely.
+#[pyclass]
+pub struct ClockProSetResult {
+    #[pyo3(get)]
+    pub index: u32,
+    #[pyo3(get)]
+    pub demoted_index: Option<u32>,
+    #[pyo3(get)]
+    pub removed_index: Option<u32>,
+    #[pyo3(get)]
+    pub removed_key: Option<String>,
 }
 
 #[pyclass]
@@ -43,6 +249,8 @@ pub struct LruCore {
     policy: Lru,
     wheel: TimerWheel,
     metadata: MetaData,
+    // set on existing key moves it to front as access does
+    promote_on_set: bool,
 }
 
 #[pyclass]
@@ -52,19 +260,34 @@ pub struct ClockProCore {
     metadata: MetaData,
 }
 
+/// LruCore that never reorders on access or set
+#[pyclass]
+pub struct FifoCore {
+    inner: LruCore,
+}
+
+#[pyclass]
+pub struct LfuCore {
+    policy: Lfu,
+    wheel: TimerWheel,
+    metadata: MetaData,
+}
+
 #[pymethods]
 impl ClockProCore {
     #[new]
-    pub fn new(size: usize) -> Self {
+    pub fn new(size: usize, min_cold: Option<usize>, max_cold: Option<usize>) -> Self {
         let mut metadata = MetaData::new(size * 2);
+        let min_cold = min_cold.unwrap_or(1);
+        let max_cold = max_cold.unwrap_or(3 * size / 4);
         Self {
-            policy: ClockPro::new(size, &mut metadata),
+            policy: ClockPro::new(size, min_cold, max_cold, &mut metadata),
             wheel: TimerWheel::new(size * 2, &mut metadata),
             metadata,
         }
     }
 
-    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<u32>, Option<String>) {
+    pub fn set(&mut self, key: &str, ttl: u128) -> ClockProSetResult {
         let entry = self.metadata.get_or_create(key);
         entry.expire = self.wheel.clock.expire_ns(ttl);
         let index = entry.index;
@@ -72,8 +295,7 @@ impl ClockProCore {
         let mut removed_key = None;
         self.wheel.schedule(index, &mut self.metadata);
         // test page, remove from Python value list only, removed page, remove all
-        let (test, removed) = self.policy.set(index, &mut self.metadata);
-        let test_index = test;
+        let (demoted_index, removed) = self.policy.set(index, &mut self.metadata);
         if let Some(i) = removed {
             let entry = &self.metadata.data[i as usize];
             removed_key = Some(entry.key.to_string());
@@ -81,7 +303,12 @@ impl ClockProCore {
             self.wheel.deschedule(i, &mut self.metadata);
             self.metadata.remove(i);
         }
-        (index, test_index, removed_index, removed_key)
+        ClockProSetResult {
+            index,
+            demoted_index,
+            removed_index,
+            removed_key,
+        }
     }
 
     pub fn remove(&mut self, key: &str) -> Option<u32> {
@@ -106,164 +333,554 @@ impl ClockProCore {
         sentinel: &PyAny,
         kh: &PyDict,
         hk: &PyDict,
-    ) {
-        let wrapper = &mut PyCache {
+        max_evictions: Option<usize>,
+    ) -> Vec<(u32, String)> {
+        let mut wrapper = PyCache {
             list: cache,
             kh,
             hk,
             sentinel,
+            expired: Vec::new(),
         };
-        self.wheel.advance(
+        self.wheel.advance_limited(
             self.wheel.clock.now_ns(),
-            wrapper,
+            max_evictions.unwrap_or(usize::MAX),
+            &mut wrapper,
             &mut self.policy,
             &mut self.metadata,
         );
+        wrapper.expired
     }
 
     pub fn clear(&mut self) {
         self.wheel.clear(&mut self.metadata);
+        self.policy.clear(&mut self.metadata);
         self.metadata.clear();
     }
 
     pub fn len(&self) -> usize {
         self.policy.len()
     }
+
+    pub fn capacity(&self) -> usize {
+        self.policy.capacity()
+    }
+
+    /// Occupancy as a fraction of capacity, test pages excluded in [0.0, 1.0]
+    pub fn fill_ratio(&self) -> f64 {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return 0.0;
+        }
+        (self.policy.len() as f64 / capacity as f64).min(1.0)
+    }
+
+    /// Keys grouped as (hot, cold, test) pages, for debugging
+    pub fn pages(&self) -> (Vec<String>, Vec<String>, Vec<String>) {
+        self.policy.pages(&self.metadata)
+    }
+
+    /// Keys in clock order as a single segment, test pages included. For asserting on
+    /// exact policy state in tests.
+    pub fn dump_order(&self) -> Vec<Vec<String>> {
+        vec![self.policy.dump_order(&self.metadata)]
+    }
+
+    /// Timer wheel level spans in nanoseconds, see where a ttl will be bucketed
+    pub fn span_boundaries(&self) -> Vec<u64> {
+        self.wheel.span_boundaries()
+    }
+
+    /// Clock time in nanoseconds of the last advance
+    pub fn current_nanos(&self) -> u64 {
+        self.wheel.current_nanos()
+    }
+
+    /// Nanoseconds until the next timer wheel tick, calling advance then expires
+    /// due entries without scanning a partial bucket
+    pub fn nanos_until_next_tick(&self) -> u64 {
+        self.wheel
+            .next_tick_nanos()
+            .saturating_sub(self.wheel.clock.now_ns() as u64)
+    }
+
+    /// Resize cache, return (index, key) of entries whose value should be dropped.
+    /// Raise ValueError if size is 0
+    #[pyo3(name = "set_capacity")]
+    fn py_set_capacity(&mut self, size: usize) -> PyResult<Vec<(u32, String)>> {
+        self.set_capacity(size).map_err(PyValueError::new_err)
+    }
+
+    /// Clock state for persistence, test pages included. ttl is remaining nanoseconds,
+    /// 0 if never expires. Hands are keys because indices change on load.
+    pub fn dump(&self) -> ClockProState {
+        let now = self.wheel.clock.now_ns();
+        let (order, hands, mem_cold) = self.policy.state(&self.metadata);
+        let entries = order
+            .iter()
+            .map(|&index| {
+                let entry = &self.metadata.data[index as usize];
+                // expired entries keep 1ns so they still expire on first advance after load
+                let ttl = match entry.expire {
+                    0 => 0,
+                    expire => expire.saturating_sub(now).clamp(1, u64::MAX as u128) as u64,
+                };
+                let (referenced, page) = entry.clock_info;
+                (entry.key.to_string(), ttl, referenced, page)
+            })
+            .collect();
+        let key = |hand: Option<u32>| hand.map(|i| self.metadata.data[i as usize].key.to_string());
+        (
+            entries,
+            (key(hands[0]), key(hands[1]), key(hands[2])),
+            mem_cold,
+        )
+    }
+
+    /// Restore dump output into this core, which must be empty and large enough
+    #[pyo3(name = "load")]
+    fn py_load(&mut self, state: ClockProState) -> PyResult<()> {
+        self.load(state).map_err(PyValueError::new_err)
+    }
+}
+
+impl ClockProCore {
+    /// Resize cache, return (index, key) of entries whose value should be dropped
+    pub fn set_capacity(&mut self, size: usize) -> Result<Vec<(u32, String)>, &'static str> {
+        if size == 0 {
+            return Err("capacity must be positive");
+        }
+        let (tests, removed) = self.policy.set_capacity(size, &mut self.metadata);
+        let mut evicted = Vec::new();
+        for index in tests.iter() {
+            if !removed.contains(index) {
+                evicted.push((*index, self.metadata.data[*index as usize].key.to_string()));
+            }
+        }
+        for index in removed {
+            evicted.push((index, self.metadata.data[index as usize].key.to_string()));
+            self.wheel.deschedule(index, &mut self.metadata);
+            self.metadata.remove(index);
+        }
+        Ok(evicted)
+    }
+
+    /// Validate state before touching anything, so a failed load leaves core empty
+    pub fn load(&mut self, state: ClockProState) -> Result<(), &'static str> {
+        let (entries, (hot, cold, test), mem_cold) = state;
+        if self.metadata.len() > 0 || self.policy.len() > 0 || self.policy.count_test > 0 {
+            return Err("cache must be empty");
+        }
+        let mut keys = AHashMap::new();
+        let (mut resident, mut tests) = (0, 0);
+        for (position, (key, _, _, page)) in entries.iter().enumerate() {
+            match *page {
+                COLD_PAGE | HOT_PAGE => resident += 1,
+                TEST_PAGE => tests += 1,
+                _ => return Err("invalid page"),
+            }
+            if keys.insert(key.as_str(), position).is_some() {
+                return Err("duplicate key");
+            }
+        }
+        if resident > self.policy.capacity() || tests > self.policy.capacity() {
+            return Err("state exceeds capacity");
+        }
+        let mut hand_positions = [None; 3];
+        for (i, hand) in [hot, cold, test].iter().enumerate() {
+            if let Some(key) = hand {
+                match keys.get(key.as_str()) {
+                    Some(&position) => hand_positions[i] = Some(position),
+                    None => return Err("hand key not found"),
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(entries.len());
+        for (key, ttl, referenced, page) in entries.iter() {
+            let entry = self.metadata.get_or_create(key);
+            entry.expire = self.wheel.clock.expire_ns(*ttl as u128);
+            entry.clock_info = (*referenced, *page);
+            let index = entry.index;
+            self.wheel.schedule(index, &mut self.metadata);
+            order.push(index);
+        }
+        let hands = hand_positions.map(|position| position.map(|p| order[p]));
+        self.policy
+            .restore(&order, hands, mem_cold, &mut self.metadata);
+        Ok(())
+    }
 }
 
 #[pymethods]
 impl TlfuCore {
+    /// admit_ties: admit candidate when its frequency equals victim's, favor recency over
+    /// frequency. Default is false, which keeps the incumbent and is more scan resistant.
+    /// early_expire_beta: time scale in nanoseconds for probabilistic early expiration,
+    /// access misses with probability exp(-remaining_ttl / beta). Disabled by default.
+    /// seed: seed the random generators of policy and should_shadow, for reproducible runs.
+    /// no_window: disable admission window, new keys go to probation directly (plain SLRU).
+    /// sketch_size: size frequency sketch for this many keys instead of cache size,
+    /// for key spaces much larger than the cache.
+    /// record_misses: count accesses of keys not in cache in frequency sketch. Default is
+    /// true, false stops probing of absent keys from inflating their frequency.
+    /// zero_ttl_means_no_cache: set with ttl 0 drops the key instead of storing a never
+    /// expiring entry. A resident key returns its index as evicted and is reported to the
+    /// eviction callback as expired, a missing key returns index 0 and nothing is stored.
+    /// Default is false.
+    /// recency_only: skip the admission contest, window evictions always replace the
+    /// probation tail (W-LRU). Eviction order is deterministic, for tests and comparing
+    /// against frequency based admission. Default is false.
+    /// count_accesses: keep an exact hit count per entry, see access_count. Default is false.
     #[new]
-    pub fn new(size: usize) -> Self {
-        let mut metadata = MetaData::new(size);
-        Self {
-            policy: TinyLfu::new(size, &mut metadata),
-            wheel: TimerWheel::new(size, &mut metadata),
-            metadata,
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        size: usize,
+        admit_ties: Option<bool>,
+        early_expire_beta: Option<f64>,
+        seed: Option<u64>,
+        no_window: Option<bool>,
+        sketch_size: Option<usize>,
+        record_misses: Option<bool>,
+        zero_ttl_means_no_cache: Option<bool>,
+        recency_only: Option<bool>,
+        count_accesses: Option<bool>,
+    ) -> Self {
+        let mut config = TlfuConfig::new(size)
+            .admit_ties(admit_ties.unwrap_or(false))
+            .early_expire_beta(early_expire_beta.unwrap_or(0.0))
+            .no_window(no_window.unwrap_or(false))
+            .record_misses(record_misses.unwrap_or(true))
+            .zero_ttl_means_no_cache(zero_ttl_means_no_cache.unwrap_or(false))
+            .recency_only(recency_only.unwrap_or(false))
+            .count_accesses(count_accesses.unwrap_or(false));
+        if let Some(seed) = seed {
+            config = config.seed(seed);
+        }
+        if let Some(sketch_size) = sketch_size {
+            config = config.sketch_size(sketch_size);
         }
+        config.build()
     }
 
-    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
-        let entry = self.metadata.get_or_create(key);
-        entry.expire = self.wheel.clock.expire_ns(ttl);
-        let index = entry.index;
-        let mut evicted_index = 0;
-        self.wheel.schedule(index, &mut self.metadata);
-        if let Some(evicted) = self.policy.set(index, &mut self.metadata) {
-            self.wheel.deschedule(evicted, &mut self.metadata);
-            self.metadata.remove(evicted);
-            evicted_index = evicted;
-        }
-        if evicted_index > 0 {
-            let evicted = &self.metadata.data[evicted_index as usize];
-            return (index, Some(evicted.index), Some(evicted.key.to_string()));
+    /// Core with exact segment capacities, for reproducing published configurations.
+    /// frozen: disable hill climbing so the admission factor stays at 0. Default is false.
+    #[staticmethod]
+    #[pyo3(name = "with_layout")]
+    fn py_with_layout(
+        window: usize,
+        probation: usize,
+        protected: usize,
+        frozen: Option<bool>,
+    ) -> Self {
+        Self::with_layout(window, probation, protected, frozen.unwrap_or(false))
+    }
+
+    /// Return (index, evicted_index, evicted_key). Index 0 means the key was not stored
+    /// (zero_ttl_means_no_cache with ttl 0), it is never a value slot, so don't write
+    /// the value. Raise RuntimeError instead of panic if policy or wheel link of key is
+    /// corrupted.
+    #[pyo3(name = "set")]
+    fn py_set(&mut self, key: &str, ttl: u128) -> PyResult<(u32, Option<u32>, Option<String>)> {
+        self.set_checked(key, ttl).map_err(PyRuntimeError::new_err)
+    }
+
+    /// Same as set, return (index, evicted) with evicted as a list of (index, key),
+    /// for bulk loaders collecting evictions in one place. Window size is not
+    /// adapted, so set is the only source of evictions and list has at most one entry.
+    pub fn set_drain(&mut self, key: &str, ttl: u128) -> (u32, Vec<(u32, String)>) {
+        let (index, evicted_index, evicted_key) = self.set(key, ttl);
+        match (evicted_index, evicted_key) {
+            (Some(i), Some(k)) => (index, vec![(i, k)]),
+            _ => (index, Vec::new()),
         }
-        (index, None, None)
     }
 
-    pub fn remove(&mut self, key: &str) -> Option<u32> {
-        if let Some(entry) = self.metadata.get(key) {
-            self.wheel.deschedule(entry, &mut self.metadata);
-            self.policy.remove(entry, &mut self.metadata);
-            self.metadata.remove(entry);
-            return Some(entry);
+    /// Key evicted by the last set, if that set was for inserted_key
+    pub fn last_eviction_for(&self, inserted_key: &str) -> Option<String> {
+        match &self.last_eviction {
+            Some((key, evicted)) if key == inserted_key => Some(evicted.clone()),
+            _ => None,
         }
-        None
     }
 
-    pub fn access(&mut self, key: &str) -> Option<u32> {
-        self.policy
-            .access(key, &self.wheel.clock, &mut self.metadata)
+    /// Exempt key from eviction, return false if key is not in cache. Pinned key still
+    /// counts toward len and expires by ttl. Once pinned keys fill the window and
+    /// probation, new keys can't be admitted.
+    pub fn pin(&mut self, key: &str) -> bool {
+        self.set_pinned(key, true)
     }
 
-    pub fn advance(
+    /// Make pinned key evictable again, return false if key is not in cache
+    pub fn unpin(&mut self, key: &str) -> bool {
+        self.set_pinned(key, false)
+    }
+
+    /// Key moved from window to probation by the last set, still in cache
+    pub fn last_set_migrated_to_main(&self) -> Option<String> {
+        self.last_migrated.clone()
+    }
+
+    /// Same as set, but if cache is full and key is new, key must win the admission
+    /// contest against current victim first. Rejected key is not inserted and admitted is false.
+    pub fn try_set(
         &mut self,
-        _py: Python,
-        cache: &PyList,
-        sentinel: &PyAny,
-        kh: &PyDict,
-        hk: &PyDict,
-    ) {
-        let wrapper = &mut PyCache {
-            list: cache,
-            kh,
-            hk,
-            sentinel,
-        };
-        self.wheel.advance(
-            self.wheel.clock.now_ns(),
-            wrapper,
-            &mut self.policy,
-            &mut self.metadata,
-        );
+        key: &str,
+        ttl: u128,
+    ) -> (Option<u32>, Option<u32>, Option<String>, bool) {
+        if self.metadata.get(key).is_none() && self.policy.len() >= self.policy.capacity() {
+            if let Some(victim) = self.policy.victim(&mut self.metadata) {
+                let vkey = self.metadata.data[victim as usize].key.to_string();
+                // no side effects, set runs the real contest on window eviction
+                if !self.policy.would_admit(key, &vkey) {
+                    return (None, None, None, false);
+                }
+            }
+        }
+        let (index, evicted_index, evicted_key) = self.set(key, ttl);
+        (Some(index), evicted_index, evicted_key, true)
     }
 
-    pub fn clear(&mut self) {
-        self.wheel.clear(&mut self.metadata);
-        self.metadata.clear();
+    /// Raise RuntimeError describing the first broken invariant, for fuzz tests
+    #[pyo3(name = "check_invariants")]
+    fn py_check_invariants(&self) -> PyResult<()> {
+        self.check_invariants().map_err(PyRuntimeError::new_err)
     }
 
-    pub fn len(&self) -> usize {
-        self.metadata.len()
+    /// Raise RuntimeError instead of panic if policy link is corrupted
+    #[pyo3(name = "remove")]
+    fn py_remove(&mut self, key: &str) -> PyResult<Option<u32>> {
+        self.remove_checked(key).map_err(PyRuntimeError::new_err)
     }
-}
 
-#[pymethods]
-impl LruCore {
-    #[new]
-    fn new(size: usize) -> Self {
-        let mut metadata = MetaData::new(size);
-        Self {
-            policy: Lru::new(size, &mut metadata),
-            wheel: TimerWheel::new(size, &mut metadata),
-            metadata,
+    /// Raise RuntimeError instead of panic if policy link is corrupted
+    #[pyo3(name = "access")]
+    fn py_access(&mut self, key: &str) -> PyResult<Option<u32>> {
+        self.access_checked(key).map_err(PyRuntimeError::new_err)
+    }
+
+    /// Same as access, but an expired entry not swept yet returns (index, true) so
+    /// stale value can be served while refreshing. Live hit returns (index, false).
+    #[pyo3(name = "access_allow_stale")]
+    fn py_access_allow_stale(&mut self, key: &str) -> PyResult<Option<(u32, bool)>> {
+        self.access_allow_stale(key)
+            .map_err(PyRuntimeError::new_err)
+    }
+
+    /// Same as access, but an expired entry not swept yet is removed now and its freed
+    /// index returned as (None, index), so a read-through caller can reuse the slot
+    #[pyo3(name = "access_or_reap")]
+    fn py_access_or_reap(&mut self, key: &str) -> PyResult<(Option<u32>, Option<u32>)> {
+        self.access_or_reap(key).map_err(PyRuntimeError::new_err)
+    }
+
+    /// Same as set, then record freq accesses in sketch so key is admitted as a warm key
+    /// when it leaves window. freq above counter max (15) has no extra effect.
+    pub fn set_with_frequency(
+        &mut self,
+        key: &str,
+        ttl: u128,
+        freq: usize,
+    ) -> (u32, Option<u32>, Option<String>) {
+        let result = self.set(key, ttl);
+        let h = self.policy.hasher.hash_one(key);
+        for _ in 0..freq.min(15) {
+            self.policy.sketch.add(h);
         }
+        result
     }
 
-    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
-        let entry = self.metadata.get_or_create(key);
-        entry.expire = self.wheel.clock.expire_ns(ttl);
-        let index = entry.index;
-        let link_id = entry.link_id;
-        let mut evicted_index = 0;
-        self.wheel.schedule(index, &mut self.metadata);
-        // new entry, insert to policy
-        if link_id == 0 {
-            if let Some(evicted) = self.policy.insert(index, &mut self.metadata) {
-                self.wheel.deschedule(evicted, &mut self.metadata);
-                self.metadata.remove(evicted);
-                evicted_index = evicted;
+    /// Bump sketch frequency of each key by count, capped at counter max,
+    /// keys are not inserted
+    pub fn prime_sketch(&mut self, entries: Vec<(String, usize)>) {
+        for (key, count) in entries.iter() {
+            let h = self.policy.hasher.hash_one(key);
+            for _ in 0..(*count).min(15) {
+                self.policy.sketch.add(h);
             }
-            if evicted_index > 0 {
-                let evicted = &self.metadata.data[evicted_index as usize];
-                return (index, Some(evicted.index), Some(evicted.key.to_string()));
+        }
+    }
+
+    /// Same as set with ttl in seconds, 0 means no expiration. Raise ValueError if ttl
+    /// is negative or NaN
+    #[pyo3(name = "set_secs")]
+    fn py_set_secs(
+        &mut self,
+        key: &str,
+        ttl_secs: f64,
+    ) -> PyResult<(u32, Option<u32>, Option<String>)> {
+        self.set_secs(key, ttl_secs).map_err(PyValueError::new_err)
+    }
+
+    /// Same as set with ttl in milliseconds
+    pub fn set_millis(&mut self, key: &str, ttl_ms: u64) -> (u32, Option<u32>, Option<String>) {
+        self.set(key, ttl_ms as u128 * 1_000_000)
+    }
+
+    pub fn set_ns(
+        &mut self,
+        namespace: &str,
+        key: &str,
+        ttl: u128,
+    ) -> (u32, Option<u32>, Option<String>) {
+        self.set(&ns_key(namespace, key), ttl)
+    }
+
+    pub fn access_ns(&mut self, namespace: &str, key: &str) -> Option<u32> {
+        self.access(&ns_key(namespace, key))
+    }
+
+    pub fn remove_ns(&mut self, namespace: &str, key: &str) -> Option<u32> {
+        self.remove(&ns_key(namespace, key))
+    }
+
+    /// Remove all keys starting with prefix, return freed indexes. O(n) over resident
+    /// keys, for frequent invalidation of a group use a separate cache per group instead.
+    pub fn remove_prefix(&mut self, prefix: &str) -> Vec<u32> {
+        let keys: Vec<String> = self
+            .metadata
+            .entries()
+            .filter(|entry| entry.key.starts_with(prefix))
+            .map(|entry| entry.key.to_string())
+            .collect();
+        keys.iter().filter_map(|key| self.remove(key)).collect()
+    }
+
+    /// Exact number of hits on key since it was set, None if key is missing or
+    /// count_accesses is not enabled
+    pub fn access_count(&self, key: &str) -> Option<u32> {
+        if !self.count_accesses {
+            return None;
+        }
+        let index = self.metadata.get(key)?;
+        Some(self.metadata.data[index as usize].access_count)
+    }
+
+    /// Key stored at index returned from set or access, None if slot is not live
+    pub fn key_at(&self, index: u32) -> Option<String> {
+        if !self.metadata.is_live(index) {
+            return None;
+        }
+        Some(self.metadata.data[index as usize].key.to_string())
+    }
+
+    /// Remove entry by index returned from set, return its key.
+    /// Return None if index is not a live entry, so recycled slots are never touched,
+    /// or if its policy link is corrupted and the entry is still live.
+    pub fn remove_by_index(&mut self, index: u32) -> Option<String> {
+        if !self.metadata.is_live(index) {
+            return None;
+        }
+        let key = self.metadata.data[index as usize].key.to_string();
+        match self.remove_checked(&key) {
+            Ok(Some(_)) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Access key, on a miss reserve key and return should_compute true to exactly one
+    /// caller, others get false until set fills the reservation. ttl in nanoseconds
+    /// bounds the reservation so a failed caller doesn't block the key, must be positive.
+    /// Return (index, should_compute, evicted_index, evicted_key), eviction is caused by
+    /// the reservation as in reserve.
+    #[pyo3(name = "get_or_compute_flag")]
+    fn py_get_or_compute_flag(&mut self, key: &str, ttl: u128) -> PyResult<ComputeFlag> {
+        self.get_or_compute_flag(key, ttl)
+            .map_err(PyValueError::new_err)
+    }
+
+    /// Drop reservation of get_or_compute_flag without setting key, such as when
+    /// compute failed. Return false if key is not reserved.
+    pub fn release_compute(&mut self, key: &str) -> bool {
+        self.cancel(key).is_some()
+    }
+
+    /// Same as set, but entry is pending: it takes a slot and counts toward capacity,
+    /// while access misses on it until commit. Evicted entry is returned as in set.
+    /// Live key, pending or not, is left as is and its index returned.
+    pub fn reserve(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
+        if let Some(index) = self.live_index(key) {
+            return (index, None, None);
+        }
+        let result = self.set(key, ttl);
+        // rejected by admission, nothing to reserve
+        if result.1 != Some(result.0) {
+            self.metadata.data[result.0 as usize].pending = true;
+        }
+        result
+    }
+
+    /// Make reserved entry visible to access, set commits too. Return false if key is
+    /// not pending, such as when reservation was evicted or expired.
+    pub fn commit(&mut self, key: &str) -> bool {
+        match self.metadata.get(key) {
+            Some(index) if self.metadata.data[index as usize].pending => {
+                self.metadata.data[index as usize].pending = false;
+                true
             }
+            _ => false,
         }
-        (index, None, None)
     }
 
-    pub fn remove(&mut self, key: &str) -> Option<u32> {
-        if let Some(index) = self.metadata.get(key) {
-            self.wheel.deschedule(index, &mut self.metadata);
-            self.policy.remove(index, &mut self.metadata);
-            self.metadata.remove(index);
-            return Some(index);
+    /// Remove reserved entry and return its index, committed entry is left in place
+    pub fn cancel(&mut self, key: &str) -> Option<u32> {
+        let index = self.metadata.get(key)?;
+        if !self.metadata.data[index as usize].pending {
+            return None;
         }
-        None
+        self.remove(key)
     }
 
-    pub fn access(&mut self, key: &str) -> Option<u32> {
-        if let Some(index) = self.metadata.get(key) {
-            let entry = &self.metadata.data[index as usize];
-            if entry.expire != 0 && entry.expire <= self.wheel.clock.now_ns() {
-                return None;
+    /// Access key and return index with remaining ttl in nanoseconds,
+    /// remaining ttl is u64::MAX if entry never expires
+    pub fn access_with_ttl(&mut self, key: &str) -> Option<(u32, u64)> {
+        let index = self.access(key)?;
+        let expire = self.metadata.data[index as usize].expire;
+        if expire == 0 {
+            return Some((index, u64::MAX));
+        }
+        let remaining = expire.saturating_sub(self.wheel.clock.now_ns());
+        Some((index, remaining.min(u64::MAX as u128) as u64))
+    }
+
+    /// Reset ttl of key without touching its policy position or frequency, ttl is
+    /// nanoseconds from now as in set, 0 means never expire. Return false if key is missing.
+    pub fn expire_at(&mut self, key: &str, ttl: u128) -> bool {
+        match self.metadata.get(key) {
+            Some(index) => {
+                self.metadata.data[index as usize].expire = self.wheel.clock.expire_ns(ttl);
+                self.wheel.schedule(index, &mut self.metadata);
+                true
             }
-            self.policy.access(index, &mut self.metadata);
-            return Some(index);
+            None => false,
         }
-        None
     }
 
+    /// expire_at for each (key, ttl), return number of keys found and refreshed
+    pub fn expire_at_many(&mut self, items: Vec<(String, u128)>) -> usize {
+        let mut count = 0;
+        for (key, ttl) in items.iter() {
+            if self.expire_at(key, *ttl) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Age sketch once if no set/access/remove happened in last idle_ns,
+    /// so stale frequencies don't block admission when traffic resumes
+    pub fn decay_if_idle(&mut self, idle_ns: u128) -> bool {
+        let now = self.wheel.clock.now_ns();
+        if now - self.last_activity > idle_ns {
+            self.policy.sketch.reset();
+            self.last_activity = now;
+            return true;
+        }
+        false
+    }
+
+    /// Remove expired entries. max_evictions stops the sweep after that many removals to
+    /// bound work per call, remaining expired entries are removed by next advance.
     pub fn advance(
         &mut self,
         _py: Python,
@@ -271,46 +888,1507 @@ impl LruCore {
         sentinel: &PyAny,
         kh: &PyDict,
         hk: &PyDict,
-    ) {
-        let wrapper = &mut PyCache {
+        max_evictions: Option<usize>,
+    ) -> Vec<(u32, String)> {
+        let mut wrapper = PyCache {
             list: cache,
             kh,
             hk,
             sentinel,
+            expired: Vec::new(),
         };
-        self.wheel.advance(
+        self.wheel.advance_limited(
             self.wheel.clock.now_ns(),
-            wrapper,
+            max_evictions.unwrap_or(usize::MAX),
+            &mut wrapper,
             &mut self.policy,
             &mut self.metadata,
         );
+        for (_, key) in wrapper.expired.iter() {
+            self.notify(key, "expired");
+        }
+        wrapper.expired
     }
 
-    pub fn clear(&mut self) {
+    /// Advance a single timer wheel level, 0 holds the shortest TTLs. Other levels
+    /// are swept on their next advance
+    pub fn advance_level(
+        &mut self,
+        level: usize,
+        cache: &PyList,
+        sentinel: &PyAny,
+        kh: &PyDict,
+        hk: &PyDict,
+    ) -> PyResult<Vec<(u32, String)>> {
+        let mut wrapper = PyCache {
+            list: cache,
+            kh,
+            hk,
+            sentinel,
+            expired: Vec::new(),
+        };
+        self.wheel
+            .advance_level(
+                level,
+                self.wheel.clock.now_ns(),
+                &mut wrapper,
+                &mut self.policy,
+                &mut self.metadata,
+            )
+            .map_err(PyValueError::new_err)?;
+        for (_, key) in wrapper.expired.iter() {
+            self.notify(key, "expired");
+        }
+        Ok(wrapper.expired)
+    }
+
+    /// Register callable invoked with (key, reason) when set evicts an entry ("evicted")
+    /// or advance removes an expired one ("expired"). None unregisters. Callback is kept
+    /// by clear, exceptions raised by it are printed and ignored.
+    pub fn set_eviction_callback(&mut self, callback: Option<PyObject>) {
+        self.eviction_callback = callback.map(|callback| {
+            Box::new(move |key: &str, reason: &str| {
+                Python::with_gil(|py| {
+                    if let Err(e) = callback.call1(py, (key, reason)) {
+                        e.print(py);
+                    }
+                })
+            }) as EvictionListener
+        });
+    }
+
+    /// Advance timer wheel without a Python value list, for callers storing values
+    /// elsewhere. Return expired keys so external store can be pruned. max_evictions
+    /// bounds removals per call as in advance, the rest are removed by later calls.
+    pub fn advance_keys(&mut self, max_evictions: Option<usize>) -> Vec<String> {
+        self.expire_keys_limited(
+            self.wheel.clock.now_ns(),
+            max_evictions.unwrap_or(usize::MAX),
+        )
+    }
+
+    /// Advance timer wheel to each clock timestamp in nanoseconds in order, return
+    /// expired keys of each step. Timestamps before the last advance are treated as it,
+    /// timestamps may run ahead of the real clock for trace replay.
+    pub fn advance_steps(&mut self, timestamps: Vec<u64>) -> Vec<Vec<String>> {
+        timestamps
+            .into_iter()
+            .map(|now| {
+                let now = now.max(self.wheel.current_nanos());
+                self.expire_keys(now as u128)
+            })
+            .collect()
+    }
+
+    /// Remove all entries. Learned frequencies are kept if preserve_sketch is true,
+    /// which assumes key popularity is not changed by the flush.
+    pub fn clear(&mut self, preserve_sketch: Option<bool>) {
+        self.wheel.clear(&mut self.metadata);
+        self.policy
+            .clear(&mut self.metadata, preserve_sketch.unwrap_or(false));
+        self.metadata.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.metadata.len()
+    }
+
+    /// Configured cache size
+    pub fn capacity(&self) -> usize {
+        self.policy.capacity()
+    }
+
+    /// Occupancy as a fraction of capacity in [0.0, 1.0]
+    pub fn fill_ratio(&self) -> f64 {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return 0.0;
+        }
+        (self.metadata.len() as f64 / capacity as f64).min(1.0)
+    }
+
+    /// Keys of each segment from front to tail, as [window, probation, protected].
+    /// For asserting on exact policy state in tests.
+    pub fn dump_order(&self) -> Vec<Vec<String>> {
+        self.policy.dump_order(&self.metadata)
+    }
+
+    /// Timer wheel level spans in nanoseconds, see where a ttl will be bucketed
+    pub fn span_boundaries(&self) -> Vec<u64> {
+        self.wheel.span_boundaries()
+    }
+
+    /// Clock time in nanoseconds of the last advance
+    pub fn current_nanos(&self) -> u64 {
+        self.wheel.current_nanos()
+    }
+
+    /// Nanoseconds until the next timer wheel tick, calling advance then expires
+    /// due entries without scanning a partial bucket
+    pub fn nanos_until_next_tick(&self) -> u64 {
+        self.wheel
+            .next_tick_nanos()
+            .saturating_sub(self.wheel.clock.now_ns() as u64)
+    }
+
+    /// Number of entries excluding expired ones not swept by advance yet.
+    /// Cost is O(scheduled entries) because the whole timer wheel is walked.
+    pub fn live_len(&self) -> usize {
+        let now = self.wheel.clock.now_ns();
+        self.metadata.len() - self.wheel.expired_count(now, &self.metadata)
+    }
+
+    /// Keys past their ttl but not swept by advance yet, nothing is removed.
+    /// Cost is O(scheduled entries) because the whole timer wheel is walked.
+    pub fn peek_expired(&self) -> Vec<String> {
+        self.wheel
+            .expired_keys(self.wheel.clock.now_ns(), &self.metadata)
+    }
+
+    /// Keys expiring within next window_ns, for refreshing them ahead of time.
+    /// Expired and never expiring keys are excluded. Cost is O(scheduled entries).
+    pub fn expiring_within(&self, window_ns: u64) -> Vec<String> {
+        let now = self.wheel.clock.now_ns();
+        self.wheel
+            .keys_expiring_between(now, now + window_ns as u128, &self.metadata)
+    }
+
+    /// Number of entries scheduled in timer wheel, entries without ttl are not counted
+    pub fn scheduled_count(&self) -> usize {
+        self.wheel.len()
+    }
+
+    /// Key hash used by the frequency sketch, feed it to BloomFilter.put_hash and
+    /// contains_hash so a key is hashed once for both
+    pub fn key_hash(&self, key: &str) -> u64 {
+        self.policy.hasher.hash_one(key)
+    }
+
+    /// True if two keys share any sketch counter, so one can inflate the other's estimate
+    pub fn keys_collide(&self, a: &str, b: &str) -> bool {
+        self.policy.sketch.collide(
+            self.policy.hasher.hash_one(a),
+            self.policy.hasher.hash_one(b),
+        )
+    }
+
+    /// Policy segment key currently belongs to: 1 window, 2 probation, 3 protected
+    pub fn segment_of(&mut self, key: &str) -> Option<u8> {
+        let index = self.metadata.get(key)?;
+        Some(self.metadata.data[index as usize].link_id)
+    }
+
+    /// True if key's frequency counters are saturated, so it was seen at least
+    /// 15 times rather than exactly 15 times.
+    pub fn is_saturated(&self, key: &str) -> bool {
+        self.policy
+            .sketch
+            .is_saturated(self.policy.hasher.hash_one(key))
+    }
+
+    /// Sample up to k resident keys, recently used ones more likely. Cost is
+    /// O(len) without allocating all keys.
+    pub fn sample_keys(&mut self, k: usize) -> Vec<String> {
+        self.policy
+            .sample(k, &self.metadata)
+            .into_iter()
+            .map(|i| self.metadata.data[i as usize].key.to_string())
+            .collect()
+    }
+
+    /// Resize frequency sketch for size keys, such as after growing the cache.
+    /// Frequencies of resident keys are carried over, others are forgotten.
+    pub fn resize_sketch(&mut self, size: usize) {
+        let hasher = &self.policy.hasher;
+        let hashes: Vec<u64> = self
+            .metadata
+            .entries()
+            .map(|entry| hasher.hash_one(entry.key.as_str()))
+            .collect();
+        self.policy.sketch.resize(size, hashes.into_iter());
+    }
+
+    /// Fraction of sketch counters at max, trending to 1.0 means admission can't tell
+    /// hot keys apart and the cache needs more capacity
+    pub fn saturation_ratio(&self) -> f64 {
+        self.policy.sketch.saturation_ratio()
+    }
+
+    /// Number of sketch resets (frequency aging) so far, compare before and after an
+    /// operation to tell if it triggered one
+    pub fn sketch_resets(&self) -> u64 {
+        self.policy.sketch.resets()
+    }
+
+    /// Sketch additions counted when the last reset ran
+    pub fn last_reset_additions(&self) -> usize {
+        self.policy.sketch.last_reset_additions()
+    }
+
+    /// Window share of capacity, 0.01 by default
+    pub fn window_ratio(&self) -> f64 {
+        self.policy.window_ratio()
+    }
+
+    /// Exponentially weighted moving average of hit ratio over accesses, 0.0 before
+    /// first access. Reacts to workload changes faster than cumulative hit ratio.
+    pub fn ewma_hit_ratio(&self) -> f64 {
+        self.ewma_hit_ratio.unwrap_or(0.0)
+    }
+
+    /// Decide if a request goes to a shadow cache, see should_shadow
+    #[pyo3(name = "should_shadow")]
+    fn py_should_shadow(&mut self, key: &str, sample_rate: f64) -> PyResult<bool> {
+        self.should_shadow(key, sample_rate)
+            .map_err(PyValueError::new_err)
+    }
+
+    /// Weight of each new access in ewma_hit_ratio, 0.01 by default
+    #[pyo3(name = "set_ewma_alpha")]
+    fn py_set_ewma_alpha(&mut self, alpha: f64) -> PyResult<()> {
+        self.set_ewma_alpha(alpha).map_err(PyValueError::new_err)
+    }
+
+    /// Keep counters at 1 instead of halving them to 0 when sketch ages, so keys
+    /// seen once before still have an edge in admission. Default is false.
+    pub fn set_reset_floor(&mut self, enabled: bool) {
+        self.policy.sketch.reset_floor = enabled;
+    }
+
+    /// Favor admission of keys present in filter, such as hot keys of a previous run.
+    /// Fill filter with put_hash(key_hash(key)) of this core, keys added by put are not
+    /// matched. Filter state is copied, later puts are not seen. Pass None to clear.
+    pub fn set_admission_prior(&mut self, prior: Option<PyRef<BloomFilter>>) {
+        self.policy.admission_prior = prior.map(|p| p.clone());
+    }
+
+    /// Restart hill climbing from defaults without dropping entries
+    pub fn reset_adaptation(&mut self) {
+        self.policy.reset_adaptation();
+    }
+
+    /// Resize protected segment at runtime, overflow is demoted to probation. If
+    /// max_demotions is set, at most that many are demoted now and the rest are left
+    /// for demote_protected, probation hits are not promoted until then. Return number demoted.
+    pub fn set_protected_capacity(
+        &mut self,
+        capacity: usize,
+        max_demotions: Option<usize>,
+    ) -> usize {
+        self.policy.set_protected_capacity(
+            capacity,
+            max_demotions.unwrap_or(usize::MAX),
+            &mut self.metadata,
+        )
+    }
+
+    /// Continue demotions deferred by set_protected_capacity, return number demoted
+    pub fn demote_protected(&mut self, max: usize) -> usize {
+        self.policy.demote_protected(max, &mut self.metadata)
+    }
+
+    /// Protected entries over capacity waiting for demote_protected
+    pub fn pending_demotions(&self) -> usize {
+        self.policy.pending_demotions()
+    }
+
+    /// Entries moved from protected to probation since creation, a measure of SLRU churn
+    pub fn demotion_count(&self) -> u64 {
+        self.policy.demotions()
+    }
+
+    /// Admit a losing candidate with sketch frequency of at least 6 with probability
+    /// 1 / one_in. Protects against hash flooding attacks, 0 (default) disables it.
+    pub fn set_random_admit_rate(&mut self, one_in: u32) {
+        self.policy.set_random_admit_rate(one_in);
+    }
+
+    pub fn random_admit_rate(&self) -> u32 {
+        self.policy.random_admit_rate()
+    }
+
+    /// Admission contest (wins, losses) since creation or last reset
+    pub fn admission_stats(&self) -> (u64, u64) {
+        (self.policy.admit_wins, self.policy.admit_losses)
+    }
+
+    pub fn reset_admission_stats(&mut self) {
+        self.policy.admit_wins = 0;
+        self.policy.admit_losses = 0;
+    }
+
+    /// Number of new entries created by set since creation or last reset, updates of
+    /// resident keys are not counted. Compare with len to see turnover.
+    pub fn total_inserts(&self) -> u64 {
+        self.total_inserts
+    }
+
+    pub fn reset_total_inserts(&mut self) {
+        self.total_inserts = 0;
+    }
+
+    /// Start or stop recording (candidate_freq, victim_freq, random_roll, admitted,
+    /// candidate_from_window) of each admission contest. candidate_from_window is false
+    /// for try_set and for new keys contesting directly because window is all pinned.
+    /// Stopping drops recorded entries.
+    pub fn enable_admit_trace(&mut self, enabled: bool) {
+        self.policy.admit_trace = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Drain recorded admission contests, empty if trace is not enabled
+    pub fn admit_trace(&mut self) -> Vec<AdmitRecord> {
+        match self.policy.admit_trace.as_mut() {
+            Some(trace) => std::mem::take(trace),
+            None => Vec::new(),
+        }
+    }
+
+    /// Start or stop removing expired entries inline when access hits them. Removed
+    /// (index, key) are kept until take_reaped, stopping drops them.
+    pub fn enable_reap_on_access(&mut self, enabled: bool) {
+        self.reaped = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Drain (index, key) of expired entries removed by access, reset their value slots
+    pub fn take_reaped(&mut self) -> Vec<(u32, String)> {
+        match self.reaped.as_mut() {
+            Some(reaped) => std::mem::take(reaped),
+            None => Vec::new(),
+        }
+    }
+
+    /// Record last capacity set/access/remove calls for offline replay, 0 disables
+    /// tracing and drops recorded events. Slots are allocated here and reused, so
+    /// recording stops allocating once slot strings have grown to key length.
+    pub fn enable_trace(&mut self, capacity: usize) {
+        self.trace_head = 0;
+        self.trace_len = 0;
+        self.trace = if capacity > 0 {
+            Some(vec![(String::new(), 0); capacity])
+        } else {
+            None
+        };
+    }
+
+    /// Drain recorded (key, op) events from oldest to newest, op is 1 for set,
+    /// 2 for access and 3 for remove
+    pub fn drain_trace(&mut self) -> Vec<(String, u8)> {
+        let trace = match self.trace.as_ref() {
+            Some(trace) => trace,
+            None => return Vec::new(),
+        };
+        let start = self.trace_head + trace.len() - self.trace_len;
+        let events = (start..start + self.trace_len)
+            .map(|i| trace[i % trace.len()].clone())
+            .collect();
+        self.trace_len = 0;
+        events
+    }
+
+    /// Number of entries with TTL beyond timer wheel top level span
+    pub fn overflow_count(&self) -> usize {
+        self.wheel.overflow_count()
+    }
+
+    /// (youngest, oldest) age of resident entries in nanoseconds, None if empty.
+    /// Age is time since key was first set, updating an existing key doesn't reset it.
+    pub fn age_extremes(&self) -> Option<(u64, u64)> {
+        let now = self.wheel.clock.now_ns();
+        let mut ages = self
+            .metadata
+            .entries()
+            .map(|entry| now.saturating_sub(entry.inserted_at).min(u64::MAX as u128) as u64);
+        let first = ages.next()?;
+        Some(ages.fold((first, first), |(min, max), age| {
+            (min.min(age), max.max(age))
+        }))
+    }
+
+    /// Return (key, index, expire) of every resident entry then clear cache, expire is
+    /// clock time in nanoseconds, 0 if never expires. Use export_entries for ttl instead.
+    pub fn drain(&mut self) -> Vec<(String, u32, u64)> {
+        let entries = self
+            .metadata
+            .entries()
+            .map(|entry| {
+                let expire = entry.expire.min(u64::MAX as u128) as u64;
+                (entry.key.to_string(), entry.index, expire)
+            })
+            .collect();
+        self.clear(None);
+        entries
+    }
+
+    /// (key, ttl, frequency) of resident entries for export. ttl is remaining nanoseconds,
+    /// 0 if never expires, so it can be passed back to set. Expired entries are skipped.
+    pub fn export_entries(&self) -> Vec<(String, u64, usize)> {
+        let now = self.wheel.clock.now_ns();
+        self.metadata
+            .entries()
+            .filter(|entry| entry.expire == 0 || entry.expire > now)
+            .map(|entry| {
+                let ttl = match entry.expire {
+                    0 => 0,
+                    expire => (expire - now).min(u64::MAX as u128) as u64,
+                };
+                let h = self.policy.hasher.hash_one(entry.key.as_str());
+                (entry.key.to_string(), ttl, self.policy.sketch.estimate(h))
+            })
+            .collect()
+    }
+
+    /// Mean sketch frequency of resident keys, 0 if empty. Rising mean means cache keeps
+    /// hotter keys over time, falling mean may indicate scan pollution. Cost is O(n).
+    pub fn resident_frequency_mean(&self) -> f64 {
+        let len = self.metadata.len();
+        if len == 0 {
+            return 0.0;
+        }
+        let total: usize = self
+            .metadata
+            .entries()
+            .map(|entry| {
+                let h = self.policy.hasher.hash_one(entry.key.as_str());
+                self.policy.sketch.estimate(h)
+            })
+            .sum();
+        total as f64 / len as f64
+    }
+
+    /// Length, hit/miss counts, segment sizes and scheduled count in one call
+    pub fn snapshot(&self) -> TlfuSnapshot {
+        let (window_len, probation_len, protected_len) = self.policy.segment_lens();
+        TlfuSnapshot {
+            len: self.metadata.len(),
+            hits: self.hits,
+            misses: self.misses,
+            window_len,
+            probation_len,
+            protected_len,
+            scheduled_count: self.wheel.len(),
+            lru_factor: self.policy.lru_factor(),
+        }
+    }
+
+    /// Move live entries into lowest free slots, their values in cache list are moved
+    /// too and old slots set to sentinel. Return released slot count, cache list can be
+    /// truncated by that many. Indexes returned before this call are no longer valid.
+    /// Slots not taken by take_reaped yet are set to sentinel and dropped from it.
+    pub fn defragment(&mut self, _py: Python, cache: &PyList, sentinel: &PyAny) -> usize {
+        self.defragment_with(
+            |index| {
+                let _ = cache.set_item(index as usize, sentinel);
+            },
+            |from, to| {
+                if let Ok(value) = cache.get_item(from as usize) {
+                    let _ = cache.set_item(to as usize, value);
+                    let _ = cache.set_item(from as usize, sentinel);
+                }
+            },
+        )
+    }
+
+    /// Move live entries into lowest free slots and release memory held by free slots.
+    /// Return (released, moves), apply moves in order as cache[to] = cache[from] and
+    /// then drop the last released slots of cache. Indexes returned before this call are
+    /// no longer valid, see defragment to move values of a Python list directly.
+    pub fn shrink_to_fit(&mut self) -> (usize, Vec<(u32, u32)>) {
+        let mut moves = Vec::new();
+        let released = self
+            .metadata
+            .shrink_to_fit(|from, to| moves.push((from, to)));
+        // reaped slots taken by a move or truncated are overwritten or dropped by caller
+        let len = self.metadata.data.len() as u32;
+        if let Some(reaped) = self.reaped.as_mut() {
+            reaped.retain(|(index, _)| *index < len && !moves.iter().any(|m| m.1 == *index));
+        }
+        (released, moves)
+    }
+}
+
+impl TlfuCore {
+    pub fn new(size: usize) -> Self {
+        let mut metadata = MetaData::new(size);
+        let wheel = TimerWheel::new(size, &mut metadata);
+        Self {
+            policy: TinyLfu::new(size, &mut metadata),
+            last_activity: wheel.clock.now_ns(),
+            last_eviction: None,
+            last_migrated: None,
+            early_expire_beta: 0.0,
+            hits: 0,
+            misses: 0,
+            total_inserts: 0,
+            ewma_hit_ratio: None,
+            ewma_alpha: 0.01,
+            eviction_callback: None,
+            reaped: None,
+            trace: None,
+            trace_head: 0,
+            trace_len: 0,
+            zero_ttl_no_cache: false,
+            count_accesses: false,
+            shadow_rng: StdRng::from_entropy(),
+            wheel,
+            metadata,
+        }
+    }
+
+    pub fn with_layout(window: usize, probation: usize, protected: usize, frozen: bool) -> Self {
+        let mut core = Self::new(window + probation + protected);
+        core.policy
+            .set_layout(window, probation, protected, &mut core.metadata);
+        core.policy.freeze_climber = frozen;
+        core
+    }
+
+    // append event to trace, oldest event is dropped when full
+    fn record(&mut self, key: &str, op: u8) {
+        if let Some(trace) = self.trace.as_mut() {
+            let slot = &mut trace[self.trace_head];
+            slot.0.clear();
+            slot.0.push_str(key);
+            slot.1 = op;
+            self.trace_head = (self.trace_head + 1) % trace.len();
+            self.trace_len = (self.trace_len + 1).min(trace.len());
+        }
+    }
+
+    /// Verify policy links, segment capacities and that every key is in policy
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.policy.check_invariants(&self.metadata)?;
+        if self.policy.len() != self.metadata.len() {
+            return Err(format!(
+                "policy len {} not match metadata len {}",
+                self.policy.len(),
+                self.metadata.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_pinned(&mut self, key: &str, pinned: bool) -> bool {
+        match self.metadata.get(key) {
+            Some(index) => {
+                self.metadata.data[index as usize].pinned = pinned;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Same as set with ttl in seconds, error if ttl is negative or NaN
+    pub fn set_secs(
+        &mut self,
+        key: &str,
+        ttl_secs: f64,
+    ) -> Result<(u32, Option<u32>, Option<String>), &'static str> {
+        if ttl_secs.is_nan() || ttl_secs < 0.0 {
+            return Err("ttl must not be negative");
+        }
+        // float to int cast saturates, inf never expires in practice
+        let mut ttl = (ttl_secs * 1e9) as u128;
+        // sub nanosecond ttl must not truncate to 0, which means no expiration
+        if ttl_secs > 0.0 {
+            ttl = ttl.max(1);
+        }
+        Ok(self.set(key, ttl))
+    }
+
+    /// Same as set, but return error without changing anything if existing entry of key
+    /// has a corrupted policy or wheel link
+    pub fn set_checked(
+        &mut self,
+        key: &str,
+        ttl: u128,
+    ) -> Result<(u32, Option<u32>, Option<String>), &'static str> {
+        if let Some(index) = self.metadata.get(key) {
+            if self.metadata.data[index as usize].link_id > 3 {
+                return Err("link id not match");
+            }
+            self.wheel.check_scheduled(index, &self.metadata)?;
+        }
+        Ok(self.set(key, ttl))
+    }
+
+    /// Index 0 is a link root, never an entry, and is returned when nothing was stored
+    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
+        self.last_activity = self.wheel.clock.now_ns();
+        self.record(key, TRACE_SET);
+        if ttl == 0 && self.zero_ttl_no_cache {
+            // existing entry is dropped too, so a stale value is never served
+            return match self.remove_checked(key).unwrap_or(None) {
+                Some(index) => {
+                    self.notify(key, "expired");
+                    (index, Some(index), Some(key.to_string()))
+                }
+                None => (0, None, None),
+            };
+        }
+        let entry = self.metadata.get_or_create(key);
+        entry.expire = self.wheel.clock.expire_ns(ttl);
+        entry.pending = false;
+        if entry.link_id == 0 {
+            entry.inserted_at = self.last_activity;
+            self.total_inserts += 1;
+        }
+        let index = entry.index;
+        let mut evicted_index = 0;
+        self.wheel.schedule(index, &mut self.metadata);
+        let evicted = self
+            .policy
+            .set_at(index, self.last_activity, &mut self.metadata);
+        self.last_migrated = self
+            .policy
+            .migrated
+            .map(|i| self.metadata.data[i as usize].key.to_string());
+        if let Some(evicted) = evicted {
+            self.wheel.deschedule(evicted, &mut self.metadata);
+            self.metadata.remove(evicted);
+            evicted_index = evicted;
+        }
+        if evicted_index > 0 {
+            let evicted_key = self.metadata.data[evicted_index as usize].key.to_string();
+            self.notify(&evicted_key, "evicted");
+            self.last_eviction = Some((key.to_string(), evicted_key.clone()));
+            return (index, Some(evicted_index), Some(evicted_key));
+        }
+        self.last_eviction = None;
+        (index, None, None)
+    }
+
+    pub fn remove_checked(&mut self, key: &str) -> Result<Option<u32>, &'static str> {
+        self.last_activity = self.wheel.clock.now_ns();
+        self.record(key, TRACE_REMOVE);
+        if let Some(entry) = self.metadata.get(key) {
+            self.policy.try_remove(entry, &mut self.metadata)?;
+            self.wheel.deschedule(entry, &mut self.metadata);
+            self.metadata.remove(entry);
+            return Ok(Some(entry));
+        }
+        Ok(None)
+    }
+
+    pub fn access_checked(&mut self, key: &str) -> Result<Option<u32>, &'static str> {
+        let (index, freed) = self.access_inner(key, self.reaped.is_some())?;
+        if let (Some(freed), Some(reaped)) = (freed, self.reaped.as_mut()) {
+            reaped.push((freed, key.to_string()));
+        }
+        Ok(index)
+    }
+
+    /// Same as access_checked, an expired resident entry is removed right away and
+    /// returned as (None, Some(index)), whether reap on access is enabled or not. Freed
+    /// index goes to caller only, take_reaped does not return it again.
+    pub fn access_or_reap(
+        &mut self,
+        key: &str,
+    ) -> Result<(Option<u32>, Option<u32>), &'static str> {
+        self.access_inner(key, true)
+    }
+
+    // return (hit index, freed index), expired entry is only removed if reap is true
+    fn access_inner(
+        &mut self,
+        key: &str,
+        reap: bool,
+    ) -> Result<(Option<u32>, Option<u32>), &'static str> {
+        self.last_activity = self.wheel.clock.now_ns();
+        self.record(key, TRACE_ACCESS);
+        let index = self
+            .policy
+            .access(key, &self.wheel.clock, &mut self.metadata)?;
+        match index {
+            Some(index) if !self.early_expire(index) => {
+                self.record_hit(true);
+                if self.count_accesses {
+                    let entry = &mut self.metadata.data[index as usize];
+                    entry.access_count = entry.access_count.saturating_add(1);
+                }
+                Ok((Some(index), None))
+            }
+            _ => {
+                self.record_hit(false);
+                let freed = if reap { self.reap_expired(key)? } else { None };
+                Ok((None, freed))
+            }
+        }
+    }
+
+    /// Reservation lives in cache as a pending entry, so it's bounded by capacity and
+    /// removed by ttl like any entry
+    pub fn get_or_compute_flag(
+        &mut self,
+        key: &str,
+        ttl: u128,
+    ) -> Result<ComputeFlag, &'static str> {
+        if ttl == 0 {
+            return Err("ttl must be greater than 0");
+        }
+        if let Some(index) = self.access(key) {
+            return Ok((Some(index), false, None, None));
+        }
+        if self.live_index(key).is_some() {
+            // pending, another caller is computing
+            return Ok((None, false, None, None));
+        }
+        let (_, evicted_index, evicted_key) = self.reserve(key, ttl);
+        Ok((None, true, evicted_index, evicted_key))
+    }
+
+    /// Same as defragment with callbacks instead of a Python list. Reaped slots are
+    /// drained to on_reaped first, because a live entry may move into them.
+    pub fn defragment_with(
+        &mut self,
+        mut on_reaped: impl FnMut(u32),
+        on_move: impl FnMut(u32, u32),
+    ) -> usize {
+        if let Some(reaped) = self.reaped.as_mut() {
+            for (index, _) in reaped.drain(..) {
+                on_reaped(index);
+            }
+        }
+        self.metadata.defragment(on_move)
+    }
+
+    // index of resident and not expired entry, pending included
+    fn live_index(&self, key: &str) -> Option<u32> {
+        let index = self.metadata.get(key)?;
+        let expire = self.metadata.data[index as usize].expire;
+        if expire != 0 && expire <= self.wheel.clock.now_ns() {
+            return None;
+        }
+        Some(index)
+    }
+
+    fn record_hit(&mut self, hit: bool) {
+        let value = if hit {
+            self.hits += 1;
+            1.0
+        } else {
+            self.misses += 1;
+            0.0
+        };
+        self.ewma_hit_ratio = Some(match self.ewma_hit_ratio {
+            Some(ratio) => ratio + self.ewma_alpha * (value - ratio),
+            None => value,
+        });
+    }
+
+    /// Sample key with probability sample_rate * (1 + frequency), capped at 1, so hot keys
+    /// are sampled more often. Uses its own seedable rng, so sampling never changes
+    /// random admission of policy. sample_rate must be in [0, 1].
+    pub fn should_shadow(&mut self, key: &str, sample_rate: f64) -> Result<bool, &'static str> {
+        // also rejects NaN
+        if !(0.0..=1.0).contains(&sample_rate) {
+            return Err("sample_rate must be in range [0, 1]");
+        }
+        let freq = self
+            .policy
+            .sketch
+            .estimate(self.policy.hasher.hash_one(key));
+        let p = (sample_rate * (1 + freq) as f64).min(1.0);
+        Ok(self.shadow_rng.gen::<f64>() < p)
+    }
+
+    /// Alpha must be in (0, 1], larger values follow recent accesses more closely
+    pub fn set_ewma_alpha(&mut self, alpha: f64) -> Result<(), &'static str> {
+        // also rejects NaN
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err("alpha must be in range (0, 1]");
+        }
+        self.ewma_alpha = alpha;
+        Ok(())
+    }
+
+    /// Stale entry counts as a miss and stays scheduled, so advance still removes
+    /// it unless set again. Reap on access is skipped for it.
+    pub fn access_allow_stale(&mut self, key: &str) -> Result<Option<(u32, bool)>, &'static str> {
+        if let Some(index) = self.metadata.get(key) {
+            let entry = &self.metadata.data[index as usize];
+            if !entry.pending && entry.expire != 0 && entry.expire <= self.wheel.clock.now_ns() {
+                self.last_activity = self.wheel.clock.now_ns();
+                self.record(key, TRACE_ACCESS);
+                self.record_hit(false);
+                return Ok(Some((index, true)));
+            }
+        }
+        Ok(self.access_checked(key)?.map(|index| (index, false)))
+    }
+
+    // remove key if it is resident but expired and return its index, early expired
+    // entry is kept
+    fn reap_expired(&mut self, key: &str) -> Result<Option<u32>, &'static str> {
+        if let Some(index) = self.metadata.get(key) {
+            let expire = self.metadata.data[index as usize].expire;
+            if expire != 0 && expire <= self.wheel.clock.now_ns() {
+                self.policy.try_remove(index, &mut self.metadata)?;
+                self.wheel.deschedule(index, &mut self.metadata);
+                self.metadata.remove(index);
+                self.notify(key, "expired");
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Same as remove_checked, corrupted entry is left in place and None is returned
+    pub fn remove(&mut self, key: &str) -> Option<u32> {
+        self.remove_checked(key).unwrap_or(None)
+    }
+
+    /// Same as access_checked, corrupted entry is treated as a miss
+    pub fn access(&mut self, key: &str) -> Option<u32> {
+        self.access_checked(key).unwrap_or(None)
+    }
+
+    // XFetch style, entry is treated as expired if remaining <= -beta * ln(rand),
+    // so probability grows as exp(-remaining / beta) when deadline approaches
+    fn early_expire(&mut self, index: u32) -> bool {
+        if self.early_expire_beta <= 0.0 {
+            return false;
+        }
+        let expire = self.metadata.data[index as usize].expire;
+        if expire == 0 {
+            return false;
+        }
+        let remaining = expire.saturating_sub(self.wheel.clock.now_ns()) as f64;
+        let r: f64 = self.policy.rng.gen();
+        remaining <= -self.early_expire_beta * r.ln()
+    }
+
+    fn expire_keys(&mut self, now: u128) -> Vec<String> {
+        self.expire_keys_limited(now, usize::MAX)
+    }
+
+    fn expire_keys_limited(&mut self, now: u128, max_evictions: usize) -> Vec<String> {
+        let mut cache = KeyCache {
+            expired: Vec::new(),
+        };
+        self.wheel.advance_limited(
+            now,
+            max_evictions,
+            &mut cache,
+            &mut self.policy,
+            &mut self.metadata,
+        );
+        for key in cache.expired.iter() {
+            self.notify(key, "expired");
+        }
+        cache.expired
+    }
+
+    fn notify(&mut self, key: &str, reason: &str) {
+        if let Some(callback) = self.eviction_callback.as_mut() {
+            callback(key, reason);
+        }
+    }
+}
+
+#[pymethods]
+impl LruCore {
+    /// promote_on_set makes set on an existing key refresh its recency as in a
+    /// standard LRU, default false only updates its ttl
+    #[new]
+    fn new(size: usize, promote_on_set: Option<bool>) -> Self {
+        let mut metadata = MetaData::new(size);
+        Self {
+            policy: Lru::new(size, &mut metadata),
+            wheel: TimerWheel::new(size, &mut metadata),
+            metadata,
+            promote_on_set: promote_on_set.unwrap_or(false),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
+        let entry = self.metadata.get_or_create(key);
+        entry.expire = self.wheel.clock.expire_ns(ttl);
+        let index = entry.index;
+        let link_id = entry.link_id;
+        let mut evicted_index = 0;
+        self.wheel.schedule(index, &mut self.metadata);
+        // new entry, insert to policy
+        if link_id == 0 {
+            if let Some(evicted) = self.policy.insert(index, &mut self.metadata) {
+                self.wheel.deschedule(evicted, &mut self.metadata);
+                self.metadata.remove(evicted);
+                evicted_index = evicted;
+            }
+            if evicted_index > 0 {
+                let evicted = &self.metadata.data[evicted_index as usize];
+                return (index, Some(evicted.index), Some(evicted.key.to_string()));
+            }
+        } else if self.promote_on_set {
+            self.policy.access(index, &mut self.metadata);
+        }
+        (index, None, None)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<u32> {
+        if let Some(index) = self.metadata.get(key) {
+            self.wheel.deschedule(index, &mut self.metadata);
+            self.policy.remove(index, &mut self.metadata);
+            self.metadata.remove(index);
+            return Some(index);
+        }
+        None
+    }
+
+    pub fn access(&mut self, key: &str) -> Option<u32> {
+        let index = self.live_index(key)?;
+        self.policy.access(index, &mut self.metadata);
+        Some(index)
+    }
+
+    pub fn advance(
+        &mut self,
+        _py: Python,
+        cache: &PyList,
+        sentinel: &PyAny,
+        kh: &PyDict,
+        hk: &PyDict,
+        max_evictions: Option<usize>,
+    ) -> Vec<String> {
+        let mut wrapper = PyCache {
+            list: cache,
+            kh,
+            hk,
+            sentinel,
+            expired: Vec::new(),
+        };
+        self.wheel.advance_limited(
+            self.wheel.clock.now_ns(),
+            max_evictions.unwrap_or(usize::MAX),
+            &mut wrapper,
+            &mut self.policy,
+            &mut self.metadata,
+        );
+        wrapper.expired.into_iter().map(|(_, key)| key).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.wheel.clear(&mut self.metadata);
+        self.policy.clear(&mut self.metadata);
+        self.metadata.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.metadata.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.policy.capacity()
+    }
+
+    /// Occupancy as a fraction of capacity in [0.0, 1.0]
+    pub fn fill_ratio(&self) -> f64 {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return 0.0;
+        }
+        (self.metadata.len() as f64 / capacity as f64).min(1.0)
+    }
+
+    /// Keys from most to least recent as a single segment. For asserting on exact
+    /// policy state in tests.
+    pub fn dump_order(&self) -> Vec<Vec<String>> {
+        vec![self.policy.link.keys(&self.metadata)]
+    }
+
+    /// Timer wheel level spans in nanoseconds, see where a ttl will be bucketed
+    pub fn span_boundaries(&self) -> Vec<u64> {
+        self.wheel.span_boundaries()
+    }
+
+    /// Clock time in nanoseconds of the last advance
+    pub fn current_nanos(&self) -> u64 {
+        self.wheel.current_nanos()
+    }
+
+    /// Nanoseconds until the next timer wheel tick, calling advance then expires
+    /// due entries without scanning a partial bucket
+    pub fn nanos_until_next_tick(&self) -> u64 {
+        self.wheel
+            .next_tick_nanos()
+            .saturating_sub(self.wheel.clock.now_ns() as u64)
+    }
+
+    /// Resize cache, return (index, key) of evicted entries. Raise ValueError if size is 0
+    #[pyo3(name = "set_capacity")]
+    fn py_set_capacity(&mut self, size: usize) -> PyResult<Vec<(u32, String)>> {
+        self.set_capacity(size).map_err(PyValueError::new_err)
+    }
+}
+
+impl LruCore {
+    /// Resize cache, return (index, key) of evicted entries
+    pub fn set_capacity(&mut self, size: usize) -> Result<Vec<(u32, String)>, &'static str> {
+        if size == 0 {
+            return Err("capacity must be positive");
+        }
+        let mut evicted = Vec::new();
+        for index in self.policy.set_capacity(size, &mut self.metadata) {
+            evicted.push((index, self.metadata.data[index as usize].key.to_string()));
+            self.wheel.deschedule(index, &mut self.metadata);
+            self.metadata.remove(index);
+        }
+        Ok(evicted)
+    }
+
+    // index of key if resident and not expired, recency is not changed
+    fn live_index(&self, key: &str) -> Option<u32> {
+        let index = self.metadata.get(key)?;
+        let expire = self.metadata.data[index as usize].expire;
+        if expire != 0 && expire <= self.wheel.clock.now_ns() {
+            return None;
+        }
+        Some(index)
+    }
+}
+
+#[pymethods]
+impl FifoCore {
+    #[new]
+    fn new(size: usize) -> Self {
+        Self {
+            inner: LruCore::new(size, Some(false)),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
+        self.inner.set(key, ttl)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<u32> {
+        self.inner.remove(key)
+    }
+
+    /// Only check expiration, FIFO order is not changed by access
+    pub fn access(&mut self, key: &str) -> Option<u32> {
+        self.inner.live_index(key)
+    }
+
+    pub fn advance(
+        &mut self,
+        py: Python,
+        cache: &PyList,
+        sentinel: &PyAny,
+        kh: &PyDict,
+        hk: &PyDict,
+        max_evictions: Option<usize>,
+    ) -> Vec<String> {
+        self.inner
+            .advance(py, cache, sentinel, kh, hk, max_evictions)
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+#[pymethods]
+impl LfuCore {
+    #[new]
+    fn new(size: usize) -> Self {
+        let mut metadata = MetaData::new(size);
+        Self {
+            policy: Lfu::new(size, &mut metadata),
+            wheel: TimerWheel::new(size, &mut metadata),
+            metadata,
+        }
+    }
+
+    pub fn set(&mut self, key: &str, ttl: u128) -> (u32, Option<u32>, Option<String>) {
+        let entry = self.metadata.get_or_create(key);
+        entry.expire = self.wheel.clock.expire_ns(ttl);
+        let index = entry.index;
+        let link_id = entry.link_id;
+        let mut evicted_index = 0;
+        self.wheel.schedule(index, &mut self.metadata);
+        // new entry, insert to policy
+        if link_id == 0 {
+            if let Some(evicted) = self.policy.insert(index, &mut self.metadata) {
+                self.wheel.deschedule(evicted, &mut self.metadata);
+                self.metadata.remove(evicted);
+                evicted_index = evicted;
+            }
+            if evicted_index > 0 {
+                let evicted = &self.metadata.data[evicted_index as usize];
+                return (index, Some(evicted.index), Some(evicted.key.to_string()));
+            }
+        }
+        (index, None, None)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<u32> {
+        if let Some(index) = self.metadata.get(key) {
+            self.wheel.deschedule(index, &mut self.metadata);
+            self.policy.remove(index, &mut self.metadata);
+            self.metadata.remove(index);
+            return Some(index);
+        }
+        None
+    }
+
+    pub fn access(&mut self, key: &str) -> Option<u32> {
+        self.policy.access(key);
+        if let Some(index) = self.metadata.get(key) {
+            let entry = &self.metadata.data[index as usize];
+            if entry.expire != 0 && entry.expire <= self.wheel.clock.now_ns() {
+                return None;
+            }
+            return Some(index);
+        }
+        None
+    }
+
+    pub fn advance(
+        &mut self,
+        _py: Python,
+        cache: &PyList,
+        sentinel: &PyAny,
+        kh: &PyDict,
+        hk: &PyDict,
+        max_evictions: Option<usize>,
+    ) -> Vec<String> {
+        let mut wrapper = PyCache {
+            list: cache,
+            kh,
+            hk,
+            sentinel,
+            expired: Vec::new(),
+        };
+        self.wheel.advance_limited(
+            self.wheel.clock.now_ns(),
+            max_evictions.unwrap_or(usize::MAX),
+            &mut wrapper,
+            &mut self.policy,
+            &mut self.metadata,
+        );
+        wrapper.expired.into_iter().map(|(_, key)| key).collect()
+    }
+
+    pub fn clear(&mut self) {
         self.wheel.clear(&mut self.metadata);
+        self.policy.link.clear(&mut self.metadata);
         self.metadata.clear();
     }
 
     pub fn len(&self) -> usize {
         self.metadata.len()
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::core::TlfuCore;
+
+    use super::{
+        ClockProCore, FifoCore, LfuCore, LruCore, TlfuConfig, TRACE_ACCESS, TRACE_REMOVE, TRACE_SET,
+    };
+    use crate::filter::BloomFilter;
+
+    #[test]
+    fn test_lru_core() {
+        let mut lru = LruCore::new(5, None);
+        for s in ["a", "b", "c", "d", "e", "f", "g", "g", "g"] {
+            lru.set(s, 0);
+        }
+        assert_eq!("gfedc", lru.policy.link.display(true, &lru.metadata));
+        assert_eq!("cdefg", lru.policy.link.display(false, &lru.metadata));
+        assert_eq!(vec![vec!["g", "f", "e", "d", "c"]], lru.dump_order());
+        assert_eq!(5, lru.metadata.len());
+    }
+
+    #[test]
+    fn test_lru_core_promote_on_set() {
+        let mut lru = LruCore::new(3, None);
+        for s in ["a", "b", "c", "a"] {
+            lru.set(s, 0);
+        }
+        assert_eq!(vec![vec!["c", "b", "a"]], lru.dump_order());
+        let (_, _, evicted) = lru.set("d", 0);
+        assert_eq!(evicted.unwrap(), "a");
+
+        let mut lru = LruCore::new(3, Some(true));
+        for s in ["a", "b", "c", "a"] {
+            lru.set(s, 0);
+        }
+        assert_eq!(vec![vec!["a", "c", "b"]], lru.dump_order());
+        let (_, _, evicted) = lru.set("d", 0);
+        assert_eq!(evicted.unwrap(), "b");
+    }
+
+    #[test]
+    fn test_core_fill_ratio() {
+        let mut lru = LruCore::new(10, None);
+        assert_eq!(lru.fill_ratio(), 0.0);
+        for i in 0..5 {
+            lru.set(&format!("key:{}", i), 0);
+        }
+        assert_eq!(lru.fill_ratio(), 0.5);
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..25 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        assert_eq!(tlfu.fill_ratio(), 0.25);
+        // test pages are not counted
+        let mut clockpro = ClockProCore::new(10, None, None);
+        for i in 0..30 {
+            clockpro.set(&format!("key:{}", i), 0);
+        }
+        assert!(clockpro.metadata.len() > 10);
+        assert_eq!(clockpro.fill_ratio(), 1.0);
+        assert_eq!(LruCore::new(0, None).fill_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_clockpro_core_set() {
+        let mut clockpro = ClockProCore::new(5, None, None);
+        for i in 0..5 {
+            let result = clockpro.set(&format!("key:{}", i), 0);
+            assert!(result.demoted_index.is_none());
+            assert!(result.removed_index.is_none());
+        }
+        let result = clockpro.set("key:5", 0);
+        assert_eq!(result.index, clockpro.metadata.get("key:5").unwrap());
+        let demoted = result.demoted_index.unwrap();
+        assert!(clockpro.metadata.data[demoted as usize]
+            .key
+            .starts_with("key:"));
+        assert!(result.removed_index.is_none());
+        assert!(result.removed_key.is_none());
+    }
+
+    #[test]
+    fn test_clockpro_core_dump_load() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut clockpro = ClockProCore::new(20, None, None);
+        for _ in 0..500 {
+            let key = format!("key:{}", rng.gen_range(0..60));
+            if clockpro.access(&key).is_none() {
+                clockpro.set(&key, 0);
+            }
+        }
+        let state = clockpro.dump();
+        assert!(state.1 .0.is_some());
+        let mut loaded = ClockProCore::new(20, None, None);
+        loaded.load(state.clone()).unwrap();
+        assert_eq!(loaded.dump(), state);
+        assert_eq!(loaded.dump_order(), clockpro.dump_order());
+        assert_eq!(loaded.pages(), clockpro.pages());
+        assert_eq!(loaded.len(), clockpro.len());
+
+        // same hands, so both evict the same keys from now on
+        for _ in 0..500 {
+            let key = format!("key:{}", rng.gen_range(0..60));
+            if clockpro.access(&key).is_none() {
+                let a = clockpro.set(&key, 0);
+                let b = loaded.set(&key, 0);
+                assert_eq!(a.removed_key, b.removed_key);
+            } else {
+                assert!(loaded.access(&key).is_some());
+            }
+        }
+        assert_eq!(loaded.dump_order(), clockpro.dump_order());
+
+        // only empty core accepts state
+        assert!(loaded.load(state.clone()).is_err());
+        let mut invalid = state.clone();
+        invalid.0[0].3 = 0;
+        assert!(ClockProCore::new(20, None, None).load(invalid).is_err());
+        let mut invalid = state.clone();
+        invalid.1 .1 = Some("missing".to_string());
+        assert!(ClockProCore::new(20, None, None).load(invalid).is_err());
+        assert!(ClockProCore::new(5, None, None)
+            .load(state.clone())
+            .is_err());
+
+        // cleared core is empty again and accepts state
+        loaded.clear();
+        assert_eq!(loaded.len(), 0);
+        assert_eq!(loaded.fill_ratio(), 0.0);
+        assert!(loaded.dump_order()[0].is_empty());
+        loaded.load(state.clone()).unwrap();
+        assert_eq!(loaded.dump(), state);
+        assert_eq!(loaded.len(), clockpro.len());
+    }
+
+    #[test]
+    fn test_fifo_core() {
+        let mut fifo = FifoCore::new(5);
+        for s in ["a", "b", "c", "d", "e"] {
+            fifo.set(s, 0);
+        }
+        // access does not reorder
+        assert!(fifo.access("a").is_some());
+        let (_, _, evicted_key) = fifo.set("f", 0);
+        assert_eq!(evicted_key.unwrap(), "a");
+        assert!(fifo.access("a").is_none());
+        assert_eq!(
+            "fedcb",
+            fifo.inner.policy.link.display(true, &fifo.inner.metadata)
+        );
+        assert_eq!(5, fifo.len());
+        fifo.remove("d");
+        assert_eq!(
+            "fecb",
+            fifo.inner.policy.link.display(true, &fifo.inner.metadata)
+        );
+        assert_eq!(4, fifo.len());
+        // set on existing key does not reorder either
+        fifo.set("b", 0);
+        assert_eq!(
+            "fecb",
+            fifo.inner.policy.link.display(true, &fifo.inner.metadata)
+        );
+    }
+
+    #[test]
+    fn test_fifo_core_clear() {
+        let mut fifo = FifoCore::new(2);
+        fifo.set("a", 0);
+        fifo.set("b", 0);
+        fifo.clear();
+        assert_eq!(0, fifo.len());
+        assert_eq!(fifo.set("c", 0).1, None);
+        assert_eq!(fifo.set("d", 0).1, None);
+        let (_, _, evicted_key) = fifo.set("e", 0);
+        assert_eq!(evicted_key.unwrap(), "c");
+        assert_eq!(2, fifo.len());
+        assert_eq!(2, fifo.inner.policy.len());
+    }
+
+    #[test]
+    fn test_lfu_core() {
+        let mut lfu = LfuCore::new(3);
+        for (key, count) in [("a", 3), ("b", 1), ("c", 2)] {
+            lfu.set(key, 0);
+            for _ in 0..count {
+                lfu.access(key);
+            }
+        }
+        let (_, _, evicted_key) = lfu.set("d", 0);
+        assert_eq!(evicted_key.unwrap(), "b");
+        // d has no access, evicted next
+        let (_, _, evicted_key) = lfu.set("e", 0);
+        assert_eq!(evicted_key.unwrap(), "d");
+        // tie between c and e broken by insertion order
+        lfu.access("e");
+        lfu.access("e");
+        let (_, _, evicted_key) = lfu.set("f", 0);
+        assert_eq!(evicted_key.unwrap(), "c");
+        assert_eq!(3, lfu.len());
+        lfu.clear();
+        assert_eq!(0, lfu.len());
+        lfu.set("g", 0);
+        assert_eq!("g", lfu.policy.link.display(true, &lfu.metadata));
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::core::TlfuCore;
+    #[test]
+    fn test_core_set_capacity() {
+        let mut lru = LruCore::new(10, None);
+        for i in 0..10 {
+            lru.set(&format!("{}", i), 0);
+        }
+        assert_eq!(10, lru.capacity());
+        let evicted = lru.set_capacity(5).unwrap();
+        assert_eq!(5, lru.capacity());
+        let keys: Vec<String> = evicted.iter().map(|e| e.1.clone()).collect();
+        assert_eq!(keys, ["0", "1", "2", "3", "4"]);
+        assert_eq!("98765", lru.policy.link.display(true, &lru.metadata));
+        assert_eq!(5, lru.len());
+        let (_, _, evicted_key) = lru.set("a", 0);
+        assert_eq!(evicted_key.unwrap(), "5");
+        assert_eq!(5, lru.len());
 
-    use super::LruCore;
+        let mut clockpro = ClockProCore::new(100, None, None);
+        for i in 0..300 {
+            let key = format!("{}", i);
+            clockpro.access(&key);
+            clockpro.set(&key, 0);
+        }
+        assert_eq!(100, clockpro.len());
+        assert_eq!(100, clockpro.capacity());
+        let evicted = clockpro.set_capacity(40).unwrap();
+        assert_eq!(40, clockpro.capacity());
+        assert_eq!(40, clockpro.len());
+        assert!(clockpro.policy.count_test <= 40);
+        assert_eq!(clockpro.metadata.len(), 40 + clockpro.policy.count_test);
+        assert!(!evicted.is_empty());
+        for i in 300..400 {
+            clockpro.set(&format!("{}", i), 0);
+            assert!(clockpro.len() <= 40);
+        }
+        clockpro.set_capacity(200).unwrap();
+        for i in 400..700 {
+            clockpro.set(&format!("{}", i), 0);
+        }
+        assert_eq!(200, clockpro.len());
+        // zero capacity is rejected and leaves cache unchanged
+        assert!(lru.set_capacity(0).is_err());
+        assert_eq!(5, lru.capacity());
+        assert!(clockpro.set_capacity(0).is_err());
+        assert_eq!(200, clockpro.capacity());
+        assert_eq!(200, clockpro.len());
+    }
 
     #[test]
-    fn test_lru_core() {
-        let mut lru = LruCore::new(5);
-        for s in ["a", "b", "c", "d", "e", "f", "g", "g", "g"] {
-            lru.set(s, 0);
+    fn test_tlfu_core_advance_keys() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", Duration::from_millis(1).as_nanos());
+        tlfu.set("b", 0);
+        tlfu.set("c", Duration::from_secs(3600).as_nanos());
+        assert!(tlfu.advance_keys(None).is_empty());
+        let now = tlfu.wheel.clock.now_ns();
+        assert_eq!(
+            tlfu.expire_keys(now + Duration::from_secs(2).as_nanos()),
+            ["a"]
+        );
+        assert_eq!(2, tlfu.len());
+        assert!(tlfu.metadata.get("a").is_none());
+    }
+
+    #[test]
+    fn test_tlfu_core_advance_keys_limited() {
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..5 {
+            tlfu.set(&format!("key:{}", i), Duration::from_secs(1).as_nanos());
         }
-        assert_eq!("gfedc", lru.policy.link.display(true, &lru.metadata));
-        assert_eq!("cdefg", lru.policy.link.display(false, &lru.metadata));
-        assert_eq!(5, lru.metadata.len());
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        assert!(tlfu.advance_keys(Some(0)).is_empty());
+        let later = tlfu.wheel.clock.now_ns() + Duration::from_secs(2).as_nanos();
+        assert_eq!(tlfu.expire_keys_limited(later, 2).len(), 2);
+        assert_eq!(tlfu.expire_keys_limited(later, 2).len(), 2);
+        assert_eq!(tlfu.expire_keys(later).len(), 1);
+        assert_eq!(tlfu.len(), 0);
+    }
+
+    #[test]
+    fn test_tlfu_core_namespace() {
+        let mut tlfu = TlfuCore::new(100);
+        let (index, _, _) = tlfu.set_ns("a", "bc", 0);
+        assert_eq!(tlfu.access_ns("a", "bc"), Some(index));
+        assert!(tlfu.access_ns("ab", "c").is_none());
+        assert!(tlfu.access("abc").is_none());
+        // plain key can't alias a namespaced one
+        let (plain, _, _) = tlfu.set("1:abc", 0);
+        assert_ne!(plain, index);
+        assert_eq!(tlfu.access_ns("a", "bc"), Some(index));
+        assert_eq!(tlfu.remove("1:abc"), Some(plain));
+        assert!(tlfu.remove_ns("ab", "c").is_none());
+        assert_eq!(tlfu.remove_ns("a", "bc"), Some(index));
+        assert_eq!(0, tlfu.len());
     }
 
     #[test]
@@ -328,4 +2406,1091 @@ mod tests {
             assert_eq!(size, tlfu.metadata.len());
         }
     }
+
+    #[test]
+    fn test_lru_core_size_zero() {
+        let mut lru = LruCore::new(0, None);
+        let (index, evicted, key) = lru.set("a", 0);
+        assert_eq!(evicted, Some(index));
+        assert_eq!(key, Some("a".to_string()));
+        assert_eq!(lru.len(), 0);
+        assert_eq!(lru.metadata.len(), 0);
+        assert!(lru.access("a").is_none());
+    }
+
+    #[test]
+    fn test_lfu_core_size_zero() {
+        let mut lfu = LfuCore::new(0);
+        let (index, evicted, key) = lfu.set("a", 0);
+        assert_eq!(evicted, Some(index));
+        assert_eq!(key, Some("a".to_string()));
+        assert_eq!(lfu.metadata.len(), 0);
+        assert_eq!(lfu.policy.link.len, 0);
+        assert!(lfu.access("a").is_none());
+    }
+
+    #[test]
+    fn test_tlfu_core_set_drain() {
+        let mut tlfu = TlfuCore::new(2);
+        let (_, evicted) = tlfu.set_drain("a", 0);
+        assert!(evicted.is_empty());
+        tlfu.set_drain("b", 0);
+        let (_, evicted) = tlfu.set_drain("c", 0);
+        assert_eq!(evicted.len(), 1);
+        assert!(tlfu.metadata.get(&evicted[0].1).is_none());
+        assert_eq!(tlfu.len(), 2);
+    }
+
+    #[test]
+    fn test_tlfu_core_last_eviction_for() {
+        let mut tlfu = TlfuCore::new(2);
+        tlfu.set("a", 0);
+        tlfu.set("b", 0);
+        assert!(tlfu.last_eviction_for("b").is_none());
+        let (_, _, evicted) = tlfu.set("c", 0);
+        assert!(evicted.is_some());
+        assert_eq!(tlfu.last_eviction_for("c"), evicted);
+        assert!(tlfu.last_eviction_for("b").is_none());
+        tlfu.set("c", 0);
+        assert!(tlfu.last_eviction_for("c").is_none());
+    }
+
+    #[test]
+    fn test_tlfu_core_last_set_migrated_to_main() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", 0);
+        assert!(tlfu.last_set_migrated_to_main().is_none());
+        tlfu.set("b", 0);
+        assert_eq!(tlfu.last_set_migrated_to_main(), Some("a".to_string()));
+        assert_eq!(tlfu.segment_of("a"), Some(2));
+        // update existing key, nothing moves
+        tlfu.set("b", 0);
+        assert!(tlfu.last_set_migrated_to_main().is_none());
+    }
+
+    #[test]
+    fn test_tlfu_core_with_layout() {
+        let mut tlfu = TlfuCore::with_layout(10, 20, 70, true);
+        assert_eq!(tlfu.capacity(), 100);
+        assert!(tlfu.policy.freeze_climber);
+        for i in 0..200 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        assert_eq!(tlfu.len(), 100);
+        assert_eq!(tlfu.policy.segment_lens(), (10, 90, 0));
+    }
+
+    #[test]
+    fn test_tlfu_core_reap_on_access() {
+        let mut tlfu = TlfuCore::new(100);
+        let ttl = Duration::from_secs(1).as_nanos();
+        tlfu.set("a", ttl);
+        tlfu.set("b", 0);
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        // disabled, expired entry stays resident
+        assert!(tlfu.access("a").is_none());
+        assert_eq!(tlfu.len(), 2);
+        assert!(tlfu.take_reaped().is_empty());
+
+        tlfu.enable_reap_on_access(true);
+        let index = tlfu.metadata.get("a").unwrap();
+        assert!(tlfu.access("a").is_none());
+        assert!(tlfu.access("b").is_some());
+        assert!(tlfu.access("c").is_none());
+        assert_eq!(tlfu.len(), 1);
+        assert_eq!(tlfu.wheel.len(), 0);
+        assert!(tlfu.metadata.get("a").is_none());
+        assert_eq!(tlfu.take_reaped(), vec![(index, "a".to_string())]);
+        assert!(tlfu.take_reaped().is_empty());
+    }
+
+    #[test]
+    fn test_tlfu_core_should_shadow() {
+        let mut tlfu = TlfuConfig::new(100).seed(1).build();
+        tlfu.set("hot", 0);
+        for _ in 0..9 {
+            tlfu.access("hot");
+        }
+        let mut policy_rng = tlfu.policy.rng.clone();
+        let (mut hot, mut cold) = (0, 0);
+        for _ in 0..10000 {
+            hot += tlfu.should_shadow("hot", 0.05).unwrap() as usize;
+            cold += tlfu.should_shadow("cold", 0.05).unwrap() as usize;
+        }
+        // hot key has frequency 10, so it is sampled about 11 times as often
+        assert!((200..800).contains(&cold));
+        assert!(hot > cold * 5);
+        assert!(!tlfu.should_shadow("hot", 0.0).unwrap());
+        assert!(tlfu.should_shadow("cold", 1.0).unwrap());
+        for rate in [-0.1, 1.1, f64::NAN] {
+            assert!(tlfu.should_shadow("cold", rate).is_err());
+        }
+        // policy rng is untouched
+        assert_eq!(policy_rng.gen::<u64>(), tlfu.policy.rng.gen::<u64>());
+    }
+
+    #[test]
+    fn test_tlfu_core_admission_prior_key_hash() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("victim", 0);
+        tlfu.access("victim");
+        let mut prior = BloomFilter::new(1000, 0.001).unwrap();
+        for i in 0..500 {
+            prior.put_hash(tlfu.key_hash(&format!("warm:{}", i)));
+        }
+        tlfu.policy.admission_prior = Some(prior);
+        for i in 0..500 {
+            assert!(tlfu.policy.admit(&format!("warm:{}", i), "victim"));
+        }
+        assert!(!tlfu.policy.admit("cold", "victim"));
+    }
+
+    #[test]
+    fn test_tlfu_core_reserve() {
+        let mut tlfu = TlfuCore::new(10);
+        let (index, _, _) = tlfu.reserve("a", 0);
+        assert_eq!(tlfu.len(), 1);
+        assert!(tlfu.access("a").is_none());
+        assert_eq!(tlfu.policy.sketch.estimate(tlfu.key_hash("a")), 0);
+        assert!(tlfu.commit("a"));
+        assert!(!tlfu.commit("a"));
+        assert_eq!(tlfu.access("a"), Some(index));
+
+        // live key is not reserved again, and cancel leaves it in place
+        assert_eq!(tlfu.reserve("a", 0).0, index);
+        assert_eq!(tlfu.access("a"), Some(index));
+        assert!(tlfu.cancel("a").is_none());
+        assert_eq!(tlfu.access("a"), Some(index));
+
+        // cancel removes pending entry only
+        let (index, _, _) = tlfu.reserve("b", 0);
+        assert_eq!(tlfu.cancel("b"), Some(index));
+        assert!(tlfu.metadata.get("b").is_none());
+        assert!(tlfu.cancel("missing").is_none());
+
+        // set fills the reservation
+        tlfu.reserve("c", 0);
+        tlfu.set("c", 0);
+        assert!(tlfu.access("c").is_some());
+        assert!(!tlfu.commit("c"));
+
+        // pending entries count toward capacity
+        for i in 0..20 {
+            tlfu.reserve(&format!("key:{}", i), 0);
+        }
+        assert_eq!(tlfu.len(), 10);
+    }
+
+    #[test]
+    fn test_tlfu_core_total_inserts() {
+        let mut tlfu = TlfuCore::new(10);
+        for i in 0..30 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        // update of resident key is not an insert
+        tlfu.set("key:29", 0);
+        assert_eq!(tlfu.total_inserts(), 30);
+        assert_eq!(tlfu.len(), 10);
+        tlfu.reset_total_inserts();
+        assert_eq!(tlfu.total_inserts(), 0);
+        tlfu.set("a", 0);
+        assert_eq!(tlfu.total_inserts(), 1);
+    }
+
+    #[test]
+    fn test_tlfu_core_access_or_reap() {
+        let mut tlfu = TlfuCore::new(100);
+        let ttl = Duration::from_secs(1).as_nanos();
+        tlfu.set("a", ttl);
+        tlfu.set("b", 0);
+        let index = tlfu.metadata.get("a").unwrap();
+        assert_eq!(tlfu.access_or_reap("a").unwrap(), (Some(index), None));
+        assert_eq!(tlfu.access_or_reap("c").unwrap(), (None, None));
+
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        assert_eq!(tlfu.access_or_reap("a").unwrap(), (None, Some(index)));
+        assert!(tlfu.metadata.get("a").is_none());
+        assert_eq!(tlfu.wheel.len(), 0);
+        assert_eq!(tlfu.len(), 1);
+        assert_eq!(tlfu.misses, 2);
+        // freed index is reused by next set
+        assert_eq!(tlfu.set("d", 0).0, index);
+
+        // not reported twice when reap on access is enabled
+        tlfu.enable_reap_on_access(true);
+        tlfu.set("e", 1);
+        let index = tlfu.metadata.get("e").unwrap();
+        assert_eq!(tlfu.access_or_reap("e").unwrap(), (None, Some(index)));
+        assert!(tlfu.take_reaped().is_empty());
+    }
+
+    #[test]
+    fn test_tlfu_core_sample_keys() {
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..50 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        let keys = tlfu.sample_keys(10);
+        assert_eq!(keys.len(), 10);
+        assert!(keys.iter().all(|k| tlfu.metadata.get(k).is_some()));
+        assert_eq!(tlfu.sample_keys(100).len(), 50);
+    }
+
+    #[test]
+    fn test_core_dump_order() {
+        let mut tlfu = TlfuCore::new(100);
+        let empty: Vec<Vec<String>> = vec![vec![], vec![], vec![]];
+        assert_eq!(tlfu.dump_order(), empty);
+        for key in ["a", "b", "c"] {
+            tlfu.set(key, 0);
+        }
+        assert_eq!(tlfu.dump_order(), vec![vec!["c"], vec!["b", "a"], vec![]]);
+        tlfu.access("a");
+        assert_eq!(tlfu.dump_order(), vec![vec!["c"], vec!["b"], vec!["a"]]);
+        // keys containing separators stay unambiguous
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a,b", 0);
+        tlfu.set("c:d", 0);
+        assert_eq!(tlfu.dump_order(), vec![vec!["c:d"], vec!["a,b"], vec![]]);
+
+        let mut clockpro = ClockProCore::new(10, None, None);
+        clockpro.set("a", 0);
+        clockpro.set("b", 0);
+        let mut keys = clockpro.dump_order().remove(0);
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_tlfu_core_pin() {
+        let mut tlfu = TlfuCore::new(100);
+        assert!(!tlfu.pin("missing"));
+        for i in 0..10 {
+            tlfu.set(&format!("pinned:{}", i), 0);
+            assert!(tlfu.pin(&format!("pinned:{}", i)));
+        }
+        for i in 0..1000 {
+            tlfu.set(&format!("key:{}", i), 0);
+            tlfu.access(&format!("key:{}", i));
+        }
+        assert_eq!(tlfu.len(), 100);
+        for i in 0..10 {
+            assert!(tlfu.access(&format!("pinned:{}", i)).is_some());
+        }
+        // pinned key still expires
+        tlfu.set("pinned:0", Duration::from_millis(1).as_nanos());
+        let now = tlfu.wheel.clock.now_ns();
+        assert_eq!(
+            tlfu.expire_keys(now + Duration::from_secs(2).as_nanos()),
+            ["pinned:0"]
+        );
+
+        assert!(tlfu.unpin("pinned:1"));
+        assert!(!tlfu.unpin("pinned:0"));
+        let index = tlfu.metadata.get("pinned:1").unwrap();
+        assert!(!tlfu.metadata.data[index as usize].pinned);
+    }
+
+    #[test]
+    fn test_tlfu_core_trace() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", 0);
+        assert!(tlfu.drain_trace().is_empty());
+        tlfu.enable_trace(3);
+        tlfu.set("a", 0);
+        tlfu.access("a");
+        tlfu.remove("a");
+        tlfu.access("b");
+        assert_eq!(
+            tlfu.drain_trace(),
+            vec![
+                ("a".to_string(), TRACE_ACCESS),
+                ("a".to_string(), TRACE_REMOVE),
+                ("b".to_string(), TRACE_ACCESS)
+            ]
+        );
+        assert!(tlfu.drain_trace().is_empty());
+        // slots are reused after a drain
+        tlfu.set("long-key", 0);
+        tlfu.access("x");
+        assert_eq!(
+            tlfu.drain_trace(),
+            vec![
+                ("long-key".to_string(), TRACE_SET),
+                ("x".to_string(), TRACE_ACCESS)
+            ]
+        );
+        tlfu.set("c", 0);
+        tlfu.enable_trace(0);
+        tlfu.set("d", 0);
+        assert!(tlfu.drain_trace().is_empty());
+    }
+
+    #[test]
+    fn test_tlfu_core_peek_expired() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", Duration::from_secs(1).as_nanos());
+        tlfu.set("b", 0);
+        tlfu.set("c", Duration::from_secs(3600).as_nanos());
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        assert_eq!(tlfu.peek_expired(), ["a"]);
+        assert_eq!(tlfu.peek_expired(), ["a"]);
+        assert_eq!(tlfu.len(), 3);
+        assert!(tlfu.metadata.get("a").is_some());
+    }
+
+    #[test]
+    fn test_tlfu_core_advance_steps() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", Duration::from_secs(1).as_nanos());
+        tlfu.set("b", Duration::from_secs(30).as_nanos());
+        tlfu.set("c", Duration::from_secs(3600).as_nanos());
+        tlfu.set("d", 0);
+        let now = tlfu.wheel.clock.now_ns() as u64;
+        let steps = tlfu.advance_steps(
+            [3, 1, 60, 7200]
+                .iter()
+                .map(|s| now + Duration::from_secs(*s).as_nanos() as u64)
+                .collect(),
+        );
+        assert_eq!(steps, vec![vec!["a"], vec![], vec!["b"], vec!["c"]]);
+        assert_eq!(tlfu.len(), 1);
+        assert!(tlfu.advance_steps(vec![]).is_empty());
+
+        // wheel is now ahead of real clock, entry set with a shorter ttl is already due
+        tlfu.set("e", Duration::from_secs(1).as_nanos());
+        let later = now + Duration::from_secs(7201).as_nanos() as u64;
+        assert_eq!(tlfu.advance_steps(vec![later]), vec![vec!["e"]]);
+        assert_eq!(tlfu.len(), 1);
+    }
+
+    #[test]
+    fn test_tlfu_core_access_allow_stale() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.enable_reap_on_access(true);
+        let (a, _, _) = tlfu.set("a", Duration::from_secs(1).as_nanos());
+        let (b, _, _) = tlfu.set("b", 0);
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        assert_eq!(tlfu.access_allow_stale("a").unwrap(), Some((a, true)));
+        assert_eq!(tlfu.access_allow_stale("b").unwrap(), Some((b, false)));
+        assert_eq!(tlfu.access_allow_stale("c").unwrap(), None);
+        // stale entry is still resident and scheduled
+        assert!(tlfu.metadata.get("a").is_some());
+        assert_eq!(tlfu.peek_expired(), ["a"]);
+        // plain access reaps it
+        assert_eq!(tlfu.access_checked("a").unwrap(), None);
+        assert_eq!(tlfu.access_allow_stale("a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_tlfu_core_expiring_within() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("expired", Duration::from_secs(1).as_nanos());
+        tlfu.set("never", 0);
+        tlfu.set("soon", Duration::from_secs(30).as_nanos());
+        tlfu.set("later", Duration::from_secs(3600).as_nanos());
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        let minute = Duration::from_secs(60).as_nanos() as u64;
+        assert_eq!(tlfu.expiring_within(minute), ["soon"]);
+        let mut keys = tlfu.expiring_within(minute * 120);
+        keys.sort();
+        assert_eq!(keys, ["later", "soon"]);
+        assert!(tlfu.expiring_within(0).is_empty());
+    }
+
+    #[test]
+    fn test_tlfu_core_try_set() {
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..100 {
+            let key = format!("key:{}", i);
+            tlfu.set(&key, 0);
+            for _ in 0..3 {
+                tlfu.access(&key);
+            }
+        }
+        assert_eq!(100, tlfu.len());
+        // cold key loses against hot victim, pre-check is not recorded as a contest
+        tlfu.enable_admit_trace(true);
+        let (index, evicted_index, evicted_key, admitted) = tlfu.try_set("new", 0);
+        assert!(tlfu.admit_trace().is_empty());
+        assert!(!admitted);
+        assert!(index.is_none() && evicted_index.is_none() && evicted_key.is_none());
+        assert!(tlfu.metadata.get("new").is_none());
+        assert_eq!(100, tlfu.len());
+        // existing key always set
+        let (index, _, _, admitted) = tlfu.try_set("key:1", 0);
+        assert!(admitted);
+        assert_eq!(index, tlfu.metadata.get("key:1"));
+        // hot key wins
+        for _ in 0..10 {
+            tlfu.access("new");
+        }
+        let (index, _, evicted_key, admitted) = tlfu.try_set("new", 0);
+        assert!(admitted);
+        assert_eq!(index, tlfu.metadata.get("new"));
+        assert!(evicted_key.is_some());
+        assert_eq!(100, tlfu.len());
+    }
+
+    #[test]
+    fn test_tlfu_core_decay_if_idle() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", 0);
+        for _ in 0..8 {
+            tlfu.access("a");
+        }
+        let h = tlfu.policy.hasher.hash_one("a");
+        assert_eq!(tlfu.policy.sketch.estimate(h), 8);
+        let idle = Duration::from_secs(3600).as_nanos();
+        assert!(!tlfu.decay_if_idle(idle));
+        assert_eq!(tlfu.policy.sketch.estimate(h), 8);
+        tlfu.wheel.clock.skip(Duration::from_secs(7200));
+        assert!(tlfu.decay_if_idle(idle));
+        assert_eq!(tlfu.policy.sketch.estimate(h), 4);
+        // decay only once per idle period
+        assert!(!tlfu.decay_if_idle(idle));
+        assert_eq!(tlfu.policy.sketch.estimate(h), 4);
+    }
+
+    #[test]
+    fn test_tlfu_core_segment_of() {
+        let mut tlfu = TlfuCore::new(100);
+        assert!(tlfu.segment_of("key:0").is_none());
+        tlfu.set("key:0", 0);
+        assert_eq!(tlfu.segment_of("key:0"), Some(1));
+        // window size is 1, key:0 moves to probation
+        tlfu.set("key:1", 0);
+        assert_eq!(tlfu.segment_of("key:0"), Some(2));
+        assert_eq!(tlfu.segment_of("key:1"), Some(1));
+        tlfu.access("key:0");
+        assert_eq!(tlfu.segment_of("key:0"), Some(3));
+    }
+
+    #[test]
+    fn test_tlfu_core_early_expire() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.policy.rng = StdRng::seed_from_u64(1);
+        let ttl = Duration::from_secs(3600).as_nanos();
+        tlfu.set("a", ttl);
+        tlfu.set("b", 0);
+        // disabled by default
+        assert!(tlfu.access("a").is_some());
+        // beta much smaller than remaining ttl, almost never early expire
+        tlfu.early_expire_beta = 1.0;
+        for _ in 0..100 {
+            assert!(tlfu.access("a").is_some());
+        }
+        // beta much larger than remaining ttl, almost always early expire
+        tlfu.early_expire_beta = 1e20;
+        let misses = (0..100).filter(|_| tlfu.access("a").is_none()).count();
+        assert!(misses > 90);
+        // entry without ttl never expires
+        assert!(tlfu.access("b").is_some());
+        assert_eq!(2, tlfu.len());
+    }
+
+    #[test]
+    fn test_tlfu_core_set_secs_millis() {
+        let mut tlfu = TlfuCore::new(100);
+        let now = tlfu.wheel.clock.now_ns();
+        let (index, _, _) = tlfu.set_secs("a", 1.5).unwrap();
+        let expire = tlfu.metadata.data[index as usize].expire - now;
+        assert!(expire >= Duration::from_millis(1500).as_nanos());
+        assert!(expire < Duration::from_millis(1600).as_nanos());
+        let (index, _, _) = tlfu.set_millis("b", 1500);
+        let expire = tlfu.metadata.data[index as usize].expire - now;
+        assert!(expire >= Duration::from_millis(1500).as_nanos());
+        assert!(expire < Duration::from_millis(1600).as_nanos());
+        let (index, _, _) = tlfu.set_secs("c", 0.0).unwrap();
+        assert_eq!(tlfu.metadata.data[index as usize].expire, 0);
+        for ttl in [-1.0, f64::NAN, f64::NEG_INFINITY] {
+            assert!(tlfu.set_secs("d", ttl).is_err());
+        }
+        assert!(tlfu.access("d").is_none());
+        // rounds up to 1ns instead of never expiring
+        let now = tlfu.wheel.clock.now_ns();
+        let (index, _, _) = tlfu.set_secs("e", 1e-12).unwrap();
+        let expire = tlfu.metadata.data[index as usize].expire;
+        assert!(expire > now);
+        tlfu.wheel.clock.skip(Duration::from_millis(1));
+        assert!(tlfu.access("e").is_none());
+    }
+
+    #[test]
+    fn test_tlfu_core_sketch_size() {
+        let tlfu = TlfuConfig::new(100).build();
+        assert_eq!(tlfu.policy.sketch.width(), 128);
+        let tlfu = TlfuConfig::new(100).sketch_size(10000).build();
+        assert_eq!(tlfu.policy.sketch.width(), 16384);
+        assert_eq!(tlfu.capacity(), 100);
+    }
+
+    #[test]
+    fn test_tlfu_core_resize_sketch() {
+        let mut tlfu = TlfuCore::new(100);
+        for _ in 0..3 {
+            tlfu.access("a");
+            tlfu.access("gone");
+        }
+        tlfu.set("a", 0);
+        tlfu.resize_sketch(10000);
+        assert_eq!(tlfu.policy.sketch.width(), 16384);
+        let h = tlfu.key_hash("a");
+        assert!(tlfu.policy.sketch.estimate(h) >= 3);
+        let h = tlfu.key_hash("gone");
+        assert_eq!(tlfu.policy.sketch.estimate(h), 0);
+    }
+
+    #[test]
+    fn test_core_next_tick() {
+        let tlfu = TlfuCore::new(100);
+        assert!(tlfu.nanos_until_next_tick() <= Duration::from_secs(2).as_nanos() as u64);
+        assert!(tlfu.current_nanos() <= tlfu.wheel.clock.now_ns() as u64);
+        let lru = LruCore::new(100, None);
+        assert!(lru.nanos_until_next_tick() <= Duration::from_secs(2).as_nanos() as u64);
+    }
+
+    #[test]
+    fn test_tlfu_core_expire_at() {
+        let mut tlfu = TlfuCore::new(100);
+        let ttl = Duration::from_secs(1).as_nanos();
+        for key in ["a", "b", "c"] {
+            tlfu.set(key, ttl);
+        }
+        let order = tlfu.dump_order();
+        let hour = Duration::from_secs(3600).as_nanos();
+        assert_eq!(
+            tlfu.expire_at_many(vec![
+                ("a".to_string(), hour),
+                ("b".to_string(), 0),
+                ("missing".to_string(), hour)
+            ]),
+            2
+        );
+        assert_eq!(tlfu.dump_order(), order);
+        assert_eq!(tlfu.scheduled_count(), 2);
+        let now = tlfu.wheel.clock.now_ns();
+        assert_eq!(
+            tlfu.expire_keys(now + Duration::from_secs(10).as_nanos()),
+            ["c"]
+        );
+        assert_eq!(tlfu.len(), 2);
+    }
+
+    #[test]
+    fn test_tlfu_core_snapshot() {
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..10 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        tlfu.set("ttl", Duration::from_secs(60).as_nanos());
+        tlfu.access("key:0");
+        tlfu.access("key:1");
+        tlfu.access("missing");
+        let snapshot = tlfu.snapshot();
+        assert_eq!(snapshot.len, 11);
+        assert_eq!((snapshot.hits, snapshot.misses), (2, 1));
+        assert_eq!(snapshot.window_len, 1);
+        assert_eq!(snapshot.probation_len, 8);
+        assert_eq!(snapshot.protected_len, 2);
+        assert_eq!(snapshot.scheduled_count, 1);
+    }
+
+    #[test]
+    fn test_tlfu_core_age_extremes() {
+        let mut tlfu = TlfuCore::new(100);
+        assert!(tlfu.age_extremes().is_none());
+        tlfu.set("a", 0);
+        tlfu.wheel.clock.skip(Duration::from_secs(5));
+        tlfu.set("b", 0);
+        // update doesn't reset age
+        tlfu.set("a", 0);
+        let (youngest, oldest) = tlfu.age_extremes().unwrap();
+        assert!(oldest >= Duration::from_secs(5).as_nanos() as u64);
+        assert!(youngest < oldest);
+        tlfu.remove("a");
+        let (youngest, oldest) = tlfu.age_extremes().unwrap();
+        assert_eq!(youngest, oldest);
+    }
+
+    #[test]
+    fn test_tlfu_core_remove_by_index() {
+        let mut tlfu = TlfuCore::new(100);
+        let (index, _, _) = tlfu.set("a", Duration::from_secs(60).as_nanos());
+        assert_eq!(tlfu.remove_by_index(index), Some("a".to_string()));
+        assert_eq!(0, tlfu.len());
+        assert_eq!(0, tlfu.scheduled_count());
+        // freed slot
+        assert!(tlfu.remove_by_index(index).is_none());
+        // recycled slot now belongs to b
+        let (index_b, _, _) = tlfu.set("b", 0);
+        assert_eq!(index, index_b);
+        assert_eq!(tlfu.remove_by_index(index_b), Some("b".to_string()));
+        // out of range and root slots
+        assert!(tlfu.remove_by_index(10000).is_none());
+        assert!(tlfu.remove_by_index(0).is_none());
+        // corrupted entry stays live, so its value must not be dropped
+        let (index, _, _) = tlfu.set("c", 0);
+        tlfu.metadata.data[index as usize].link_id = 9;
+        assert!(tlfu.remove_by_index(index).is_none());
+        assert!(tlfu.metadata.is_live(index));
+    }
+
+    #[test]
+    fn test_tlfu_core_zero_ttl_no_cache() {
+        let mut tlfu = TlfuConfig::new(100).zero_ttl_means_no_cache(true).build();
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        let sink = dropped.clone();
+        tlfu.eviction_callback = Some(Box::new(move |key: &str, reason: &str| {
+            sink.lock()
+                .unwrap()
+                .push((key.to_string(), reason.to_string()));
+        }));
+        // missing key allocates nothing
+        let slots = tlfu.metadata.data.len();
+        let (index, evicted_index, evicted_key) = tlfu.set("a", 0);
+        assert_eq!(index, 0);
+        assert!(evicted_index.is_none());
+        assert!(evicted_key.is_none());
+        // 0 is a root sentinel, not a slot a value could be stored in
+        assert!(tlfu.metadata.data[0].key.starts_with("__root:"));
+        assert_eq!(tlfu.metadata.data.len(), slots);
+        assert_eq!(tlfu.len(), 0);
+        assert!(tlfu.access("a").is_none());
+        assert!(dropped.lock().unwrap().is_empty());
+        // existing entry is dropped
+        let (index, _, _) = tlfu.set("b", Duration::from_secs(60).as_nanos());
+        let (dropped_index, evicted, key) = tlfu.set("b", 0);
+        assert_eq!(dropped_index, index);
+        assert_eq!(evicted, Some(index));
+        assert_eq!(key.unwrap(), "b");
+        assert_eq!(
+            *dropped.lock().unwrap(),
+            vec![("b".to_string(), "expired".to_string())]
+        );
+        assert_eq!(tlfu.len(), 0);
+        assert_eq!(tlfu.scheduled_count(), 0);
+        assert!(tlfu.check_invariants().is_ok());
+        // default keeps never expiring entry
+        let mut tlfu = TlfuCore::new(100);
+        assert_eq!(tlfu.set("a", 0).1, None);
+        assert_eq!(tlfu.len(), 1);
+    }
+
+    #[test]
+    fn test_tlfu_core_ewma_hit_ratio() {
+        let mut tlfu = TlfuCore::new(100);
+        assert_eq!(tlfu.ewma_hit_ratio(), 0.0);
+        tlfu.set("a", 0);
+        tlfu.access("a");
+        assert_eq!(tlfu.ewma_hit_ratio(), 1.0);
+        assert!(tlfu.set_ewma_alpha(0.0).is_err());
+        assert!(tlfu.set_ewma_alpha(f64::NAN).is_err());
+        tlfu.set_ewma_alpha(0.5).unwrap();
+        tlfu.access("b");
+        assert_eq!(tlfu.ewma_hit_ratio(), 0.5);
+        tlfu.access("b");
+        assert_eq!(tlfu.ewma_hit_ratio(), 0.25);
+        tlfu.access("a");
+        assert_eq!(tlfu.ewma_hit_ratio(), 0.625);
+    }
+
+    #[test]
+    fn test_tlfu_core_remove_prefix() {
+        let mut tlfu = TlfuCore::new(100);
+        let mut indexes = Vec::new();
+        for i in 0..5 {
+            let (index, _, _) =
+                tlfu.set(&format!("user:1:{}", i), Duration::from_secs(60).as_nanos());
+            indexes.push(index);
+            tlfu.set(&format!("user:2:{}", i), 0);
+        }
+        let mut removed = tlfu.remove_prefix("user:1:");
+        removed.sort();
+        assert_eq!(removed, indexes);
+        assert_eq!(tlfu.len(), 5);
+        assert_eq!(tlfu.scheduled_count(), 0);
+        assert!(tlfu.access("user:2:0").is_some());
+        assert!(tlfu.remove_prefix("user:1:").is_empty());
+        assert!(tlfu.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_tlfu_core_recency_only() {
+        let mut tlfu = TlfuConfig::new(100).recency_only(true).build();
+        // keep accessed keys in probation
+        tlfu.set_protected_capacity(0, None);
+        for i in 0..100 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        // hot probation tail is still evicted
+        for _ in 0..10 {
+            tlfu.access("key:0");
+        }
+        for i in 0..10 {
+            let (_, _, evicted) = tlfu.set(&format!("new:{}", i), 0);
+            assert_eq!(evicted.unwrap(), format!("key:{}", i));
+        }
+    }
+
+    #[test]
+    fn test_tlfu_core_access_count() {
+        let mut tlfu = TlfuConfig::new(100).count_accesses(true).build();
+        tlfu.set("a", 0);
+        assert_eq!(tlfu.access_count("a"), Some(0));
+        for _ in 0..3 {
+            tlfu.access("a");
+        }
+        tlfu.access("b");
+        assert_eq!(tlfu.access_count("a"), Some(3));
+        assert_eq!(tlfu.access_count("b"), None);
+        // count restarts when slot is reused
+        tlfu.remove("a");
+        tlfu.set("c", 0);
+        assert_eq!(tlfu.access_count("c"), Some(0));
+        // disabled by default
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", 0);
+        tlfu.access("a");
+        assert_eq!(tlfu.access_count("a"), None);
+    }
+
+    #[test]
+    fn test_tlfu_core_drain() {
+        let mut tlfu = TlfuCore::new(100);
+        let (a, _, _) = tlfu.set("a", 0);
+        let (b, _, _) = tlfu.set("b", Duration::from_secs(60).as_nanos());
+        let expire = tlfu.metadata.data[b as usize].expire as u64;
+        let mut drained = tlfu.drain();
+        drained.sort();
+        assert_eq!(
+            drained,
+            vec![("a".to_string(), a, 0), ("b".to_string(), b, expire)]
+        );
+        assert_eq!(tlfu.len(), 0);
+        assert_eq!(tlfu.scheduled_count(), 0);
+        assert!(tlfu.drain().is_empty());
+        tlfu.set("c", 0);
+        assert!(tlfu.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_tlfu_core_get_or_compute_flag() {
+        let mut tlfu = TlfuCore::new(100);
+        let ttl = Duration::from_secs(10).as_nanos();
+        assert!(tlfu.get_or_compute_flag("a", 0).is_err());
+        assert_eq!(
+            tlfu.get_or_compute_flag("a", ttl).unwrap(),
+            (None, true, None, None)
+        );
+        assert_eq!(
+            tlfu.get_or_compute_flag("a", ttl).unwrap(),
+            (None, false, None, None)
+        );
+        // reservation takes a slot
+        assert_eq!(tlfu.len(), 1);
+        let (index, _, _) = tlfu.set("a", 0);
+        assert_eq!(
+            tlfu.get_or_compute_flag("a", ttl).unwrap(),
+            (Some(index), false, None, None)
+        );
+        // released reservation is handed out again
+        assert!(tlfu.get_or_compute_flag("b", ttl).unwrap().1);
+        assert!(tlfu.release_compute("b"));
+        assert!(!tlfu.release_compute("b"));
+        assert!(tlfu.get_or_compute_flag("b", ttl).unwrap().1);
+        // filled key is not released
+        assert!(!tlfu.release_compute("a"));
+        // timed out reservation
+        let ttl = Duration::from_secs(1).as_nanos();
+        assert!(tlfu.get_or_compute_flag("c", ttl).unwrap().1);
+        assert!(!tlfu.get_or_compute_flag("c", ttl).unwrap().1);
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        assert!(tlfu.get_or_compute_flag("c", ttl).unwrap().1);
+        // reservations are bounded by capacity
+        for i in 0..1000 {
+            tlfu.get_or_compute_flag(&format!("key:{}", i), ttl)
+                .unwrap();
+        }
+        assert!(tlfu.len() <= 100);
+    }
+
+    #[test]
+    fn test_tlfu_core_key_at() {
+        let mut tlfu = TlfuCore::new(100);
+        let (index, _, _) = tlfu.set("a", 0);
+        assert_eq!(tlfu.key_at(index), Some("a".to_string()));
+        tlfu.remove("a");
+        assert!(tlfu.key_at(index).is_none());
+        let (index_b, _, _) = tlfu.set("b", 0);
+        assert_eq!(index, index_b);
+        assert_eq!(tlfu.key_at(index), Some("b".to_string()));
+        assert!(tlfu.key_at(0).is_none());
+        assert!(tlfu.key_at(10000).is_none());
+    }
+
+    #[test]
+    fn test_tlfu_core_export_entries() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.set("a", 0);
+        tlfu.set("b", Duration::from_secs(60).as_nanos());
+        tlfu.set("c", 1);
+        for _ in 0..3 {
+            tlfu.access("a");
+        }
+        let mut entries = tlfu.export_entries();
+        entries.sort();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], ("a".to_string(), 0, 3));
+        assert_eq!(entries[1].0, "b");
+        assert!(entries[1].1 > 0 && entries[1].1 <= Duration::from_secs(60).as_nanos() as u64);
+    }
+
+    #[test]
+    fn test_tlfu_core_clear() {
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..200 {
+            tlfu.set(&format!("key:{}", i), Duration::from_secs(60).as_nanos());
+            tlfu.access(&format!("key:{}", i % 10));
+        }
+        let h = tlfu.policy.hasher.hash_one("key:1");
+        let estimate = tlfu.policy.sketch.estimate(h);
+        assert!(estimate > 0);
+        tlfu.clear(Some(true));
+        assert_eq!(tlfu.len(), 0);
+        assert_eq!(tlfu.policy.len(), 0);
+        assert_eq!(tlfu.scheduled_count(), 0);
+        assert_eq!(tlfu.policy.sketch.estimate(h), estimate);
+        for i in 0..200 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        assert_eq!(tlfu.len(), 100);
+        assert_eq!(tlfu.policy.len(), 100);
+        tlfu.clear(None);
+        assert_eq!(tlfu.len(), 0);
+        assert_eq!(tlfu.policy.len(), 0);
+        assert_eq!(tlfu.policy.sketch.estimate(h), 0);
+    }
+
+    #[test]
+    fn test_tlfu_core_set_with_frequency() {
+        let mut tlfu = TlfuCore::new(100);
+        for i in 0..100 {
+            tlfu.set(&format!("key:{}", i), 0);
+            tlfu.access(&format!("key:{}", i));
+        }
+        tlfu.set_with_frequency("warm", 0, 5);
+        let h = tlfu.policy.hasher.hash_one("warm");
+        assert_eq!(tlfu.policy.sketch.estimate(h), 5);
+        // warm key wins admission when pushed out of window
+        let (_, _, evicted_key) = tlfu.set("next", 0);
+        assert_ne!(evicted_key.unwrap(), "warm");
+        assert!(tlfu.access("warm").is_some());
+        tlfu.set_with_frequency("hot", 0, 100);
+        let h = tlfu.policy.hasher.hash_one("hot");
+        assert_eq!(tlfu.policy.sketch.estimate(h), 15);
+    }
+
+    #[test]
+    fn test_tlfu_core_prime_sketch() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.prime_sketch(vec![("a".to_string(), 3), ("b".to_string(), 1000)]);
+        assert_eq!(tlfu.len(), 0);
+        let h = tlfu.policy.hasher.hash_one("a");
+        assert_eq!(tlfu.policy.sketch.estimate(h), 3);
+        let h = tlfu.policy.hasher.hash_one("b");
+        assert_eq!(tlfu.policy.sketch.estimate(h), 15);
+    }
+
+    #[test]
+    fn test_tlfu_core_corrupted_link() {
+        let mut tlfu = TlfuCore::new(100);
+        let (index, _, _) = tlfu.set("a", 0);
+        tlfu.set("b", 0);
+        tlfu.metadata.data[index as usize].link_id = 9;
+        assert!(tlfu.access_checked("a").is_err());
+        assert!(tlfu.remove_checked("a").is_err());
+        assert!(tlfu.access("a").is_none());
+        // other keys still work
+        assert!(tlfu.access_checked("b").unwrap().is_some());
+        assert!(tlfu.remove_checked("b").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_tlfu_core_corrupted_wheel_link() {
+        let mut tlfu = TlfuCore::new(100);
+        let ttl = Duration::from_secs(1).as_nanos();
+        let (index, _, _) = tlfu.set("a", ttl);
+        tlfu.set("b", ttl);
+        assert!(tlfu.set_checked("a", ttl).is_ok());
+        tlfu.metadata.data[index as usize].wheel_link_id = 200;
+        assert!(tlfu.set_checked("a", ttl).is_err());
+        let (index, _, _) = tlfu.set("c", 0);
+        tlfu.metadata.data[index as usize].link_id = 9;
+        assert!(tlfu.set_checked("c", 0).is_err());
+        // unchecked paths skip corrupted entry instead of panicking
+        tlfu.set("a", ttl);
+        let expired = tlfu.expire_keys(tlfu.wheel.clock.now_ns() + 2 * ttl);
+        assert!(expired.len() <= 2);
+    }
+
+    #[test]
+    fn test_tlfu_core_check_invariants() {
+        let mut tlfu = TlfuCore::new(100);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..5000 {
+            let key = format!("key:{}", rng.gen_range(0..300));
+            match rng.gen_range(0..3) {
+                0 => {
+                    tlfu.set(&key, 0);
+                }
+                1 => {
+                    tlfu.access(&key);
+                }
+                _ => {
+                    tlfu.remove(&key);
+                }
+            }
+        }
+        assert!(tlfu.check_invariants().is_ok());
+        let key = tlfu.sample_keys(1).remove(0);
+        let index = tlfu.metadata.get(&key).unwrap();
+        tlfu.metadata.data[index as usize].link_id = 9;
+        assert!(tlfu.check_invariants().is_err());
+    }
+
+    #[test]
+    fn test_tlfu_core_defragment() {
+        let mut tlfu = TlfuCore::new(100);
+        let ttl = Duration::from_secs(60).as_nanos();
+        for i in 0..100 {
+            tlfu.set(&format!("key:{}", i), if i % 2 == 0 { ttl } else { 0 });
+        }
+        for i in (0..100).filter(|i| i % 4 < 2) {
+            tlfu.remove(&format!("key:{}", i));
+        }
+        let mut moved = 0;
+        let released = tlfu.metadata.defragment(|_, _| moved += 1);
+        assert!(released > 0 && moved > 0);
+        assert_eq!(tlfu.len(), 50);
+        assert_eq!(tlfu.policy.len(), 50);
+        assert_eq!(tlfu.scheduled_count(), 25);
+        for i in (0..100).filter(|i| i % 4 >= 2) {
+            let index = tlfu.access(&format!("key:{}", i)).unwrap();
+            assert_eq!(tlfu.metadata.data[index as usize].key, format!("key:{}", i));
+        }
+        let expired = tlfu.expire_keys(tlfu.wheel.clock.now_ns() + 2 * ttl);
+        assert_eq!(expired.len(), 25);
+        assert_eq!(tlfu.len(), 25);
+        assert_eq!(tlfu.policy.len(), 25);
+    }
+
+    #[test]
+    fn test_tlfu_core_shrink_to_fit() {
+        let mut tlfu = TlfuCore::new(100);
+        let mut cache: Vec<Option<String>> = vec![None; tlfu.metadata.data.len() + 100];
+        for i in 0..100 {
+            let key = format!("key:{}", i);
+            let (index, _, _) = tlfu.set(&key, 0);
+            cache[index as usize] = Some(key);
+        }
+        // keep a few entries near the end alive
+        for i in (0..95).filter(|i| i % 10 != 0) {
+            let index = tlfu.remove(&format!("key:{}", i)).unwrap();
+            cache[index as usize] = None;
+        }
+        let (released, moves) = tlfu.shrink_to_fit();
+        assert!(!moves.is_empty());
+        for (from, to) in moves {
+            cache[to as usize] = cache[from as usize].take();
+        }
+        cache.truncate(cache.len() - released);
+        assert!(tlfu.check_invariants().is_ok());
+        for i in (0..100).filter(|i| i % 10 == 0 || *i >= 95) {
+            let key = format!("key:{}", i);
+            let index = tlfu.access(&key).unwrap();
+            assert_eq!(cache[index as usize].as_deref(), Some(key.as_str()));
+        }
+        // no values left behind in released or moved-from slots
+        assert!(released > 0);
+        assert_eq!(cache.iter().flatten().count(), tlfu.len());
+    }
+
+    #[test]
+    fn test_tlfu_core_defragment_reaped() {
+        let mut tlfu = TlfuCore::new(100);
+        tlfu.enable_reap_on_access(true);
+        tlfu.set("a", Duration::from_secs(1).as_nanos());
+        for i in 0..10 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        tlfu.wheel.clock.skip(Duration::from_secs(2));
+        let index = tlfu.metadata.get("a").unwrap();
+        assert!(tlfu.access("a").is_none());
+        let mut reaped = Vec::new();
+        let mut moved = Vec::new();
+        tlfu.defragment_with(|i| reaped.push(i), |from, to| moved.push((from, to)));
+        assert_eq!(reaped, vec![index]);
+        // a live entry moved into reaped slot, so it must not be reported again
+        assert!(moved.iter().any(|(_, to)| *to == index));
+        assert!(tlfu.take_reaped().is_empty());
+        assert_eq!(tlfu.len(), 10);
+    }
+
+    #[test]
+    fn test_tlfu_core_eviction_callback() {
+        let mut tlfu = TlfuCore::new(100);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = events.clone();
+        tlfu.eviction_callback = Some(Box::new(move |key: &str, reason: &str| {
+            listener_events
+                .lock()
+                .unwrap()
+                .push((key.to_string(), reason.to_string()));
+        }));
+        tlfu.set("a", Duration::from_millis(1).as_nanos());
+        for i in 0..100 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        // callback survives clear
+        tlfu.clear(None);
+        for i in 0..100 {
+            tlfu.set(&format!("key:{}", i), 0);
+        }
+        tlfu.set("b", Duration::from_millis(1).as_nanos());
+        tlfu.expire_keys(tlfu.wheel.clock.now_ns() + Duration::from_secs(2).as_nanos());
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events[..2].iter().all(|(_, reason)| reason == "evicted"));
+        assert_eq!(events[2], ("b".to_string(), "expired".to_string()));
+    }
+
+    #[test]
+    fn test_tlfu_core_resident_frequency_mean() {
+        let mut tlfu = TlfuCore::new(100);
+        assert_eq!(tlfu.resident_frequency_mean(), 0.0);
+        tlfu.set("a", 0);
+        tlfu.set("b", 0);
+        for _ in 0..3 {
+            tlfu.access("a");
+        }
+        tlfu.access("b");
+        assert_eq!(tlfu.resident_frequency_mean(), 2.0);
+    }
+
+    #[test]
+    fn test_tlfu_core_is_saturated() {
+        let mut tlfu = TlfuCore::new(100);
+        for _ in 0..14 {
+            tlfu.access("a");
+        }
+        assert!(!tlfu.is_saturated("a"));
+        assert_eq!(tlfu.saturation_ratio(), 0.0);
+        tlfu.access("a");
+        assert!(tlfu.is_saturated("a"));
+        assert!(tlfu.saturation_ratio() > 0.0);
+        tlfu.access("a");
+        assert!(tlfu.is_saturated("a"));
+        assert!(!tlfu.is_saturated("b"));
+    }
 }
@@ -1,7 +1,8 @@
 use ahash::RandomState;
-use pyo3::prelude::*;
+use pyo3::{exceptions::PyValueError, prelude::*};
 
 #[pyclass]
+#[derive(Clone)]
 pub struct BloomFilter {
     insertions: usize,
     bits_mask: usize,
@@ -11,28 +12,47 @@ pub struct BloomFilter {
     hasher: RandomState,
 }
 
-#[pymethods]
 impl BloomFilter {
-    #[new]
-    fn new(insertions: usize, fpp: f64) -> Self {
+    pub(crate) fn new(insertions: usize, fpp: f64) -> Result<Self, &'static str> {
+        if insertions == 0 {
+            return Err("insertions must be greater than 0");
+        }
+        // also rejects NaN
+        if !(fpp > 0.0 && fpp < 1.0) {
+            return Err("fpp must be in range (0, 1)");
+        }
         let ln2 = 2f64.ln();
         let factor = -fpp.ln() / (ln2 * ln2);
         let mut bits = ((insertions as f64 * factor) as usize).next_power_of_two();
         if bits == 0 {
             bits = 1
         }
-        Self {
+        let slice_count = (ln2 * bits as f64 / insertions as f64) as usize;
+        Ok(Self {
             insertions,
             bits_mask: bits - 1,
-            slice_count: (ln2 * bits as f64 / insertions as f64) as usize,
+            slice_count: slice_count.max(1),
             bits: vec![0; (bits + 63) / 64],
             additions: 0,
             hasher: RandomState::new(),
-        }
+        })
+    }
+}
+
+#[pymethods]
+impl BloomFilter {
+    #[new]
+    fn py_new(insertions: usize, fpp: f64) -> PyResult<Self> {
+        Self::new(insertions, fpp).map_err(PyValueError::new_err)
     }
 
     pub fn put(&mut self, key: &str) {
-        let h = self.hasher.hash_one(key);
+        self.put_hash(self.hasher.hash_one(key));
+    }
+
+    /// Same as put with a precomputed key hash, such as TlfuCore.key_hash. A filter
+    /// must be fed by one hashing path only, mixing put and put_hash gives false negatives.
+    pub fn put_hash(&mut self, h: u64) {
         self.additions += 1;
         if self.additions == self.insertions {
             self.reset();
@@ -59,7 +79,11 @@ impl BloomFilter {
     }
 
     pub fn contains(&self, key: &str) -> bool {
-        let h = self.hasher.hash_one(key);
+        self.contains_hash(self.hasher.hash_one(key))
+    }
+
+    /// Same as contains with a precomputed key hash, pair with put_hash
+    pub fn contains_hash(&self, h: u64) -> bool {
         let mut o = true;
         for i in 0..self.slice_count {
             let hash = h + i as u64 * (h >> 32);
@@ -72,15 +96,41 @@ impl BloomFilter {
         self.bits = vec![0; self.bits.len()];
         self.additions = 0;
     }
+
+    /// Number of puts since last reset
+    pub fn additions(&self) -> usize {
+        self.additions
+    }
+
+    /// Number of puts that triggers an automatic reset
+    pub fn insertions(&self) -> usize {
+        self.insertions
+    }
+
+    /// Number of puts that can be added before the automatic reset discards all keys
+    pub fn remaining_capacity(&self) -> usize {
+        self.insertions.saturating_sub(self.additions + 1)
+    }
+
+    /// True if next put triggers the automatic reset, rotate to a new filter before it
+    pub fn is_full(&self) -> bool {
+        self.remaining_capacity() == 0
+    }
+
+    /// Remove all keys, same as the automatic reset
+    pub fn clear(&mut self) {
+        self.reset();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::BloomFilter;
+    use crate::core::TlfuCore;
 
     #[test]
     fn test_filter() {
-        let mut bf = BloomFilter::new(100, 0.001);
+        let mut bf = BloomFilter::new(100, 0.001).unwrap();
         assert_eq!(bf.slice_count, 14);
         assert_eq!(bf.bits.len(), 32);
         for i in 0..100 {
@@ -100,4 +150,52 @@ mod tests {
             assert!(exist);
         }
     }
+
+    #[test]
+    fn test_filter_remaining_capacity() {
+        let mut bf = BloomFilter::new(10, 0.01).unwrap();
+        assert_eq!(bf.remaining_capacity(), 9);
+        for i in 0..9 {
+            assert!(!bf.is_full());
+            bf.put(&format!("key:{}", i));
+        }
+        assert_eq!(bf.remaining_capacity(), 0);
+        assert!(bf.is_full());
+        assert!(bf.contains("key:0"));
+        // next put resets
+        bf.put("key:9");
+        assert!(!bf.contains("key:0"));
+        assert_eq!(bf.remaining_capacity(), 9);
+    }
+
+    #[test]
+    fn test_filter_hash() {
+        let core = TlfuCore::new(100);
+        let mut bf = BloomFilter::new(100, 0.001).unwrap();
+        for i in 0..40 {
+            bf.put_hash(core.key_hash(&format!("key:{}", i)));
+        }
+        for i in 0..40 {
+            assert!(bf.contains_hash(core.key_hash(&format!("key:{}", i))));
+        }
+        assert_eq!(bf.additions(), 40);
+    }
+
+    #[test]
+    fn test_filter_new_invalid() {
+        assert!(BloomFilter::new(0, 0.01).is_err());
+        for fpp in [0.0, -0.1, 1.0, 1.5, f64::NAN] {
+            assert!(BloomFilter::new(100, fpp).is_err());
+        }
+        let mut bf = BloomFilter::new(1000, 0.0001).unwrap();
+        assert!(bf.slice_count >= 1);
+        for i in 0..500 {
+            bf.put(&format!("key:{}", i));
+        }
+        for i in 0..500 {
+            assert!(bf.contains(&format!("key:{}", i)));
+        }
+        let bf = BloomFilter::new(1, 0.9).unwrap();
+        assert_eq!(bf.slice_count, 1);
+    }
 }
@@ -0,0 +1,69 @@
+use crate::{
+    metadata::{Link, MetaData},
+    policy::Policy,
+    sketch::CountMinSketch,
+};
+use ahash::RandomState;
+
+/// Plain LFU for study and small caches, eviction scans all entries so it's O(n).
+pub struct Lfu {
+    pub link: Link, // id is 1, insertion order
+    sketch: CountMinSketch,
+    hasher: RandomState,
+}
+
+impl Policy for Lfu {
+    fn remove(&mut self, index: u32, metadata: &mut MetaData) {
+        self.link.remove(index, metadata);
+    }
+}
+
+impl Lfu {
+    pub fn new(maxsize: usize, metadata: &mut MetaData) -> Lfu {
+        Lfu {
+            link: Link::new(1, maxsize as u32, metadata),
+            sketch: CountMinSketch::new(maxsize),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Insert new entry, evict least frequent resident first if full. Entry itself is
+    /// returned if lfu can't hold it, such as zero capacity
+    pub fn insert(&mut self, index: u32, metadata: &mut MetaData) -> Option<u32> {
+        let mut evicted = None;
+        if self.link.len >= self.link.capacity {
+            if let Some(victim) = self.victim(metadata) {
+                self.link.remove(victim, metadata);
+                evicted = Some(victim);
+            }
+        }
+        match self.link.try_insert_front(index, metadata) {
+            Ok(removed) => removed.or(evicted),
+            Err(_) => Some(index),
+        }
+    }
+
+    /// Record frequency of key, both hit and miss count
+    pub fn access(&mut self, key: &str) {
+        self.sketch.add(self.hasher.hash_one(key));
+    }
+
+    // scan from oldest entry, first minimum wins so ties evict older entry
+    fn victim(&self, metadata: &MetaData) -> Option<u32> {
+        let mut victim = None;
+        let mut min = usize::MAX;
+        let mut index = metadata.data[self.link.root as usize].prev;
+        while index != self.link.root {
+            let entry = &metadata.data[index as usize];
+            let count = self
+                .sketch
+                .estimate(self.hasher.hash_one(entry.key.as_str()));
+            if count < min {
+                min = count;
+                victim = Some(index);
+            }
+            index = entry.prev;
+        }
+        victim
+    }
+}
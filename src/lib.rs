@@ -2,6 +2,7 @@ use pyo3::prelude::*;
 mod clockpro;
 mod core;
 mod filter;
+mod lfu;
 mod lru;
 mod metadata;
 mod policy;
@@ -12,8 +13,12 @@ mod tlfu;
 #[pymodule]
 fn theine_core(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<core::TlfuCore>()?;
+    m.add_class::<core::TlfuSnapshot>()?;
     m.add_class::<core::LruCore>()?;
     m.add_class::<core::ClockProCore>()?;
+    m.add_class::<core::ClockProSetResult>()?;
+    m.add_class::<core::FifoCore>()?;
+    m.add_class::<core::LfuCore>()?;
     m.add_class::<filter::BloomFilter>()?;
     Ok(())
 }
@@ -20,14 +20,22 @@ impl Lru {
         }
     }
 
+    /// Insert entry to front, return evicted entry. Entry itself is returned
+    /// if lru can't hold it, such as zero capacity
     pub fn insert(&mut self, index: u32, metadata: &mut MetaData) -> Option<u32> {
-        self.link.insert_front(index, metadata)
+        self.link
+            .try_insert_front(index, metadata)
+            .unwrap_or(Some(index))
     }
 
     pub fn access(&mut self, index: u32, metadata: &mut MetaData) {
         self.link.touch(index, metadata)
     }
 
+    pub fn clear(&mut self, metadata: &mut MetaData) {
+        self.link.clear(metadata);
+    }
+
     pub fn capacity(&self) -> usize {
         self.link.capacity as usize
     }
@@ -35,12 +43,27 @@ impl Lru {
     pub fn len(&self) -> usize {
         self.link.len as usize
     }
+
+    /// Update capacity and pop entries from tail until link fits, return popped entries
+    pub fn set_capacity(&mut self, maxsize: usize, metadata: &mut MetaData) -> Vec<u32> {
+        self.link.capacity = maxsize as u32;
+        let mut evicted = Vec::new();
+        while self.link.len > self.link.capacity {
+            match self.link.pop_tail(metadata) {
+                Some(index) => evicted.push(index),
+                None => break,
+            }
+        }
+        evicted
+    }
 }
 
 pub struct Slru {
     pub probation: Link, // id is 2
     pub protected: Link, // id is 3
     maxsize: usize,
+    // entries moved from protected to probation since created
+    pub demotions: u64,
 }
 
 impl Slru {
@@ -50,6 +73,7 @@ impl Slru {
             maxsize,
             probation: Link::new(2, maxsize as u32, metadata),
             protected: Link::new(3, protected_cap as u32, metadata),
+            demotions: 0,
         }
     }
 
@@ -79,29 +103,76 @@ impl Slru {
         self.probation.tail(metadata)
     }
 
-    pub fn access(&mut self, index: u32, metadata: &mut MetaData) {
+    pub fn access(&mut self, index: u32, metadata: &mut MetaData) -> Result<(), &'static str> {
         let entry = &mut metadata.data[index as usize];
         match entry.link_id {
             2 => {
-                if self.protected.capacity > 0 {
+                // protected over capacity has deferred demotions, don't promote until
+                // they are done
+                if self.protected.len > self.protected.capacity {
+                    self.probation.touch(index, metadata);
+                } else if self.protected.capacity > 0 {
                     self.probation.remove(index, metadata);
                     if let Some(evicted) = self.protected.insert_front(index, metadata) {
                         self.probation.insert_front(evicted, metadata);
+                        self.demotions += 1;
                     }
                 }
             }
             3 => self.protected.touch(index, metadata),
-            _ => unreachable!(),
+            _ => return Err("link id not match"),
         }
+        Ok(())
     }
 
-    pub fn remove(&mut self, index: u32, metadata: &mut MetaData) {
+    pub fn remove(&mut self, index: u32, metadata: &mut MetaData) -> Result<(), &'static str> {
         let entry = &mut metadata.data[index as usize];
         match entry.link_id {
             2 => self.probation.remove(index, metadata),
             3 => self.protected.remove(index, metadata),
-            _ => unreachable!(),
+            _ => return Err("link id not match"),
         };
+        Ok(())
+    }
+
+    pub fn clear(&mut self, metadata: &mut MetaData) {
+        self.probation.clear(metadata);
+        self.protected.clear(metadata);
+    }
+
+    /// Update total capacity of empty slru, protected keeps 80% of it
+    pub fn set_maxsize(&mut self, maxsize: usize) {
+        self.maxsize = maxsize;
+        self.probation.capacity = maxsize as u32;
+        self.protected.capacity = (maxsize as f64 * 0.8) as u32;
+    }
+
+    /// Update protected capacity, demote at most max_demotions overflow entries from
+    /// protected tail to probation front, return number demoted
+    pub fn set_protected_capacity(
+        &mut self,
+        capacity: usize,
+        max_demotions: usize,
+        metadata: &mut MetaData,
+    ) -> usize {
+        self.protected.capacity = capacity.min(self.maxsize) as u32;
+        self.demote(max_demotions, metadata)
+    }
+
+    /// Demote at most max entries while protected is over capacity, return number demoted
+    pub fn demote(&mut self, max: usize, metadata: &mut MetaData) -> usize {
+        let mut count = 0;
+        while count < max && self.protected.len > self.protected.capacity {
+            match self.protected.pop_tail(metadata) {
+                Some(index) => {
+                    self.probation.insert_front(index, metadata);
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        self.demotions += count as u64;
+        count
     }
 
     pub fn protected_capacity(&self) -> usize {
@@ -18,6 +18,12 @@ pub struct Entry {
     pub wheel_next: u32,
     pub expire: u128,
     pub clock_info: (bool, u8),
+    pub inserted_at: u128,
+    pub pinned: bool,
+    // exact hits since set, only counted if enabled on core
+    pub access_count: u32,
+    // reserved slot without a value yet, reads skip it until committed
+    pub pending: bool,
 }
 
 impl Entry {
@@ -34,6 +40,10 @@ impl Entry {
             wheel_index: (0, 0),
             expire: 0,
             clock_info: (false, COLD_PAGE), // new entry should be cold page and no reference
+            inserted_at: 0,
+            pinned: false,
+            access_count: 0,
+            pending: false,
         }
     }
 }
@@ -87,6 +97,13 @@ impl Link {
         let next_entry = &mut metadata.data[old_next as usize];
         next_entry.prev = index;
         self.len += 1;
+        debug_assert!(
+            self.len <= self.capacity,
+            "link {} over capacity: {} > {}",
+            self.id,
+            self.len,
+            self.capacity
+        );
         if removed > 0 {
             return Some(removed);
         }
@@ -112,6 +129,13 @@ impl Link {
         let prev_entry = &mut metadata.data[old_prev as usize];
         prev_entry.next = index;
         self.len += 1;
+        debug_assert!(
+            self.len <= self.capacity,
+            "link {} over capacity: {} > {}",
+            self.id,
+            self.len,
+            self.capacity
+        );
         if removed > 0 {
             return Some(removed);
         }
@@ -152,11 +176,12 @@ impl Link {
         Some(removed_index)
     }
 
-    /// Remove entry from wheel link, decrease len
-    pub fn remove_wheel(&mut self, index: u32, metadata: &mut MetaData) {
+    /// Remove entry from wheel link, decrease len. Return false and leave link unchanged
+    /// if entry is not in this link
+    pub fn remove_wheel(&mut self, index: u32, metadata: &mut MetaData) -> bool {
         let entry = &mut metadata.data[index as usize];
         if entry.wheel_link_id != self.id {
-            panic!("link id not match");
+            return false;
         }
         entry.wheel_link_id = 0;
         entry.wheel_index = (0, 0);
@@ -169,6 +194,7 @@ impl Link {
         let next_entry = &mut metadata.data[next as usize];
         next_entry.wheel_prev = prev;
         self.len -= 1;
+        true
     }
 
     /// Insert entry to link front and return evicted key
@@ -176,6 +202,19 @@ impl Link {
         self.insert(index, self.root, metadata)
     }
 
+    /// Same as insert_front, but return error without inserting if link would stay
+    /// over capacity, which happens if capacity is 0 or was shrunk below len
+    pub fn try_insert_front(
+        &mut self,
+        index: u32,
+        metadata: &mut MetaData,
+    ) -> Result<Option<u32>, &'static str> {
+        if self.capacity == 0 || self.len > self.capacity {
+            return Err("link over capacity");
+        }
+        Ok(self.insert_front(index, metadata))
+    }
+
     /// Insert entry to link front and return evicted key
     pub fn insert_front_wheel(&mut self, index: u32, metadata: &mut MetaData) {
         self.insert_wheel(index, self.root, metadata)
@@ -200,10 +239,69 @@ impl Link {
         None
     }
 
-    /// Move entry to front of link
+    /// Walk link and verify entry link ids, prev/next pointers and len
+    pub fn check(&self, metadata: &MetaData) -> Result<(), String> {
+        let mut count = 0;
+        let mut prev = self.root;
+        let mut current = metadata.data[self.root as usize].next;
+        while current != self.root {
+            if count >= self.len {
+                return Err(format!("link {} longer than len {}", self.id, self.len));
+            }
+            let entry = &metadata.data[current as usize];
+            if entry.link_id != self.id {
+                return Err(format!(
+                    "entry {} in link {} has link id {}",
+                    entry.key, self.id, entry.link_id
+                ));
+            }
+            if entry.prev != prev {
+                return Err(format!(
+                    "entry {} in link {} has bad prev",
+                    entry.key, self.id
+                ));
+            }
+            count += 1;
+            prev = current;
+            current = entry.next;
+        }
+        if count != self.len {
+            return Err(format!(
+                "link {} has {} entries, len is {}",
+                self.id, count, self.len
+            ));
+        }
+        Ok(())
+    }
+
+    /// Move pinned entries at tail to front so tail can be evicted,
+    /// return false if link is not empty and every entry is pinned
+    pub fn rotate_pinned(&mut self, metadata: &mut MetaData) -> bool {
+        for _ in 0..self.len {
+            match self.tail(metadata) {
+                Some(tail) if metadata.data[tail as usize].pinned => self.touch(tail, metadata),
+                _ => return true,
+            }
+        }
+        self.len == 0
+    }
+
+    /// Move entry to front of link, len is unchanged so nothing is evicted even if
+    /// link is over capacity
     pub fn touch(&mut self, index: u32, metadata: &mut MetaData) {
-        self.remove(index, metadata);
-        self.insert_front(index, metadata);
+        let entry = &metadata.data[index as usize];
+        if entry.link_id != self.id || entry.prev == self.root {
+            return;
+        }
+        let (prev, next) = (entry.prev, entry.next);
+        metadata.data[prev as usize].next = next;
+        metadata.data[next as usize].prev = prev;
+        let first = metadata.data[self.root as usize].next;
+        metadata.data[first as usize].prev = index;
+        metadata.data[self.root as usize].next = index;
+        let entry = &mut metadata.data[index as usize];
+        entry.prev = self.root;
+        entry.next = first;
     }
 
     /// Clear link, only keep root
@@ -213,6 +311,23 @@ impl Link {
         entry.next = entry.index;
         entry.wheel_prev = entry.index;
         entry.wheel_next = entry.index;
+        self.len = 0;
+    }
+
+    /// Iterate entry indexes from front to tail
+    pub fn iter<'a>(&'a self, metadata: &'a MetaData) -> Iter<'a> {
+        Iter {
+            metadata,
+            root: self.root,
+            index: metadata.data[self.root as usize].next,
+        }
+    }
+
+    /// Keys from front to tail
+    pub fn keys(&self, metadata: &MetaData) -> Vec<String> {
+        self.iter(metadata)
+            .map(|i| metadata.data[i as usize].key.to_string())
+            .collect()
     }
 
     /// Creates an iterator that yields mutable references to values in the link
@@ -293,7 +408,29 @@ impl Link {
     }
 }
 
-/// An iterator that yields mutable references to entries in the link
+/// An iterator that yields entry indexes in the link
+pub struct Iter<'a> {
+    metadata: &'a MetaData,
+    index: u32,
+    root: u32,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.root == self.index {
+            None
+        } else {
+            let current = self.index;
+            self.index = self.metadata.data[current as usize].next;
+            Some(current)
+        }
+    }
+}
+
+/// An iterator that yields entries in the wheel link, it stops early at an entry of
+/// another link instead of following a corrupted chain
 pub struct IterWheel<'a> {
     metadata: &'a MetaData,
     index: u32,
@@ -311,7 +448,8 @@ impl<'a> Iterator for IterWheel<'a> {
             let current = self.index;
             let entry = &self.metadata.data[current as usize];
             if entry.wheel_link_id != self._id {
-                panic!("wheel link not match");
+                self.index = self.root;
+                return None;
             }
             self.index = entry.wheel_next;
             Some((current, entry.key.to_string(), entry.expire))
@@ -337,7 +475,7 @@ impl MetaData {
     }
 
     // get entry by key
-    pub fn get(&mut self, key: &str) -> Option<u32> {
+    pub fn get(&self, key: &str) -> Option<u32> {
         if let Some(index) = self.keys.get(key) {
             return Some(*index);
         }
@@ -393,6 +531,86 @@ impl MetaData {
     pub fn len(&self) -> usize {
         self.keys.len()
     }
+
+    // move entry from one slot to a free slot, fix key map and link neighbours
+    fn move_entry(&mut self, from: usize, to: usize) {
+        self.data.swap(from, to);
+        let entry = &mut self.data[to];
+        entry.index = to as u32;
+        let (link_id, prev, next) = (entry.link_id, entry.prev, entry.next);
+        let (wheel_link_id, wheel_prev, wheel_next) =
+            (entry.wheel_link_id, entry.wheel_prev, entry.wheel_next);
+        if let Some(index) = self.keys.get_mut(&entry.key) {
+            *index = to as u32;
+        }
+        if link_id > 0 {
+            self.data[prev as usize].next = to as u32;
+            self.data[next as usize].prev = to as u32;
+        }
+        if wheel_link_id > 0 {
+            self.data[wheel_prev as usize].wheel_next = to as u32;
+            self.data[wheel_next as usize].wheel_prev = to as u32;
+        }
+    }
+
+    // move live entries from highest slots into lowest free slots and truncate the tail,
+    // on_move(from, to) is called for each moved entry, return dropped slot count.
+    // Only valid if links are the only holders of indexes besides the caller.
+    pub fn defragment(&mut self, mut on_move: impl FnMut(u32, u32)) -> usize {
+        let mut free = vec![false; self.data.len()];
+        for index in self.empty.iter() {
+            free[*index as usize] = true;
+        }
+        self.empty.sort_unstable();
+        let mut end = self.data.len();
+        let mut next_free = 0;
+        loop {
+            while end > 0 && free[end - 1] {
+                end -= 1;
+            }
+            if next_free >= self.empty.len() || self.empty[next_free] as usize >= end {
+                break;
+            }
+            let from = end - 1;
+            let to = self.empty[next_free] as usize;
+            next_free += 1;
+            self.move_entry(from, to);
+            on_move(from as u32, to as u32);
+            free[to] = false;
+            free[from] = true;
+        }
+        let released = self.data.len() - end;
+        self.data.truncate(end);
+        self.empty = self.empty[next_free..]
+            .iter()
+            .copied()
+            .filter(|index| (*index as usize) < end)
+            .collect();
+        released
+    }
+
+    /// True if index points to an entry still owned by its key, not a free or root slot
+    pub fn is_live(&self, index: u32) -> bool {
+        match self.data.get(index as usize) {
+            Some(entry) => self.keys.get(&entry.key) == Some(&index),
+            None => false,
+        }
+    }
+
+    /// Iterate entries with a key, root nodes and free slots are skipped
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.keys.values().map(|index| &self.data[*index as usize])
+    }
+
+    // compact live entries into lowest slots as defragment does, then release unused
+    // capacity. on_move(from, to) is called for each moved entry, return dropped slot count
+    pub fn shrink_to_fit(&mut self, on_move: impl FnMut(u32, u32)) -> usize {
+        let released = self.defragment(on_move);
+        self.data.shrink_to_fit();
+        self.empty.shrink_to_fit();
+        self.keys.shrink_to_fit();
+        released
+    }
 }
 
 #[cfg(test)]
@@ -482,6 +700,25 @@ mod tests {
         assert_eq!(link.display(false, &metadata), "");
     }
 
+    #[test]
+    fn test_link_try_insert_front() {
+        let mut metadata = MetaData::new(10);
+        let mut link = Link::new(1, 2, &mut metadata);
+        for key in ["a", "b", "c"] {
+            let index = metadata.get_or_create(key).index;
+            assert!(link.try_insert_front(index, &mut metadata).is_ok());
+        }
+        assert_eq!(link.len, 2);
+        link.capacity = 1;
+        let index = metadata.get_or_create("d").index;
+        assert!(link.try_insert_front(index, &mut metadata).is_err());
+        assert_eq!(link.len, 2);
+
+        let mut empty = Link::new(2, 0, &mut metadata);
+        assert!(empty.try_insert_front(index, &mut metadata).is_err());
+        assert_eq!(empty.len, 0);
+    }
+
     #[test]
     fn test_link_wheel() {
         let mut metadata = MetaData::new(5);
@@ -526,9 +763,18 @@ mod tests {
         assert_eq!(link.display_wheel(true, &metadata), "a");
         assert_eq!(link.display_wheel(false, &metadata), "a");
         let index = metadata.get("a").unwrap();
-        link.remove_wheel(index, &mut metadata);
+        assert!(link.remove_wheel(index, &mut metadata));
         assert_eq!(link.display_wheel(true, &metadata), "");
         assert_eq!(link.display_wheel(false, &metadata), "");
+        // not in link
+        assert!(!link.remove_wheel(index, &mut metadata));
+        // iter stops at entry of other link
+        link.insert_front_wheel(metadata.get("a").unwrap(), &mut metadata);
+        link.insert_front_wheel(metadata.get("b").unwrap(), &mut metadata);
+        let index = metadata.get("b").unwrap();
+        metadata.data[index as usize].wheel_link_id = 6;
+        assert_eq!(link.iter_wheel(&metadata).count(), 0);
+        assert!(!link.remove_wheel(index, &mut metadata));
     }
 
     #[test]
@@ -546,7 +792,79 @@ mod tests {
         assert_eq!(metadata.len(), 1);
         metadata.remove(index_b);
         assert_eq!(metadata.len(), 0);
+        assert!(link.len > 0);
+        link.clear(&mut metadata);
+        assert_eq!(link.len, 0);
+        assert_eq!(link.iter(&metadata).count(), 0);
         metadata.clear();
         assert_eq!(metadata.len(), 0);
     }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut metadata = MetaData::new(100);
+        let mut link = Link::new(1, 100, &mut metadata);
+        for i in 0..100 {
+            let index = metadata.get_or_create(&format!("{}", i)).index;
+            link.insert_front(index, &mut metadata);
+        }
+        assert_eq!(metadata.data.len(), 101);
+        // free a slot in the middle and the 50 highest slots
+        for i in (50..100).chain([10]) {
+            let index = metadata.get(&format!("{}", i)).unwrap();
+            link.remove(index, &mut metadata);
+            metadata.remove(index);
+        }
+        // highest live entry moves into the middle hole
+        let mut moves = Vec::new();
+        assert_eq!(
+            metadata.shrink_to_fit(|from, to| moves.push((from, to))),
+            51
+        );
+        assert_eq!(moves, vec![(50, 11)]);
+        assert_eq!(metadata.data.len(), 50);
+        assert_eq!(metadata.len(), 49);
+        assert!(metadata.data.capacity() < 100);
+        for i in 0..50 {
+            if i == 10 {
+                continue;
+            }
+            let index = metadata.get(&format!("{}", i)).unwrap();
+            assert_eq!(metadata.data[index as usize].key, format!("{}", i));
+        }
+        assert_eq!(link.len, 49);
+        assert_eq!(link.iter(&metadata).count(), 49);
+        // no holes left, data grows again
+        assert_eq!(metadata.get_or_create("a").index, 50);
+        assert_eq!(metadata.shrink_to_fit(|_, _| {}), 0);
+    }
+
+    #[test]
+    fn test_defragment() {
+        let mut metadata = MetaData::new(100);
+        let mut link = Link::new(1, 100, &mut metadata);
+        for i in 0..100 {
+            let index = metadata.get_or_create(&format!("{}", i)).index;
+            link.insert_front(index, &mut metadata);
+        }
+        for i in (0..100).filter(|i| i % 3 == 0) {
+            let index = metadata.get(&format!("{}", i)).unwrap();
+            link.remove(index, &mut metadata);
+            metadata.remove(index);
+        }
+        let expected = link.display(true, &metadata);
+        let mut moved = Vec::new();
+        assert_eq!(metadata.defragment(|from, to| moved.push((from, to))), 34);
+        assert_eq!(metadata.data.len(), 67);
+        assert_eq!(metadata.len(), 66);
+        assert!(moved.iter().all(|(from, to)| to < from));
+        assert_eq!(link.display(true, &metadata), expected);
+        for i in (0..100).filter(|i| i % 3 != 0) {
+            let index = metadata.get(&format!("{}", i)).unwrap();
+            assert_eq!(metadata.data[index as usize].index, index);
+            assert_eq!(metadata.data[index as usize].key, format!("{}", i));
+        }
+        assert_eq!(metadata.get_or_create("a").index, 67);
+        assert_eq!(metadata.defragment(|_, _| ()), 0);
+    }
 }
@@ -6,6 +6,11 @@ pub struct CountMinSketch {
     table: Vec<u64>,
     additions: usize,
     sample_size: usize,
+    // keep counters at 1 on reset instead of halving them to 0
+    pub reset_floor: bool,
+    resets: u64,
+    // additions when the last reset ran
+    last_reset_additions: usize,
 }
 
 impl CountMinSketch {
@@ -22,6 +27,9 @@ impl CountMinSketch {
             sample_size: 10 * counter_size,
             table,
             block_mask,
+            reset_floor: false,
+            resets: 0,
+            last_reset_additions: 0,
         }
     }
 
@@ -64,15 +72,59 @@ impl CountMinSketch {
         }
     }
 
-    fn reset(&mut self) {
+    pub fn reset(&mut self) {
         let mut count = 0;
+        self.resets += 1;
+        self.last_reset_additions = self.additions;
 
         for i in self.table.iter_mut() {
             count += (*i & ONE_MASK).count_ones();
-            *i = (*i >> 1) & RESET_MASK;
+            let halved = (*i >> 1) & RESET_MASK;
+            if self.reset_floor {
+                // counters equal to 1, their higher bits are all zero
+                let high = (*i >> 1 | *i >> 2 | *i >> 3) & ONE_MASK;
+                *i = halved | (*i & ONE_MASK & !high);
+            } else {
+                *i = halved;
+            }
         }
 
-        self.additions = (self.additions - ((count >> 2) as usize)) >> 1;
+        // floored counters can outnumber additions after a few resets
+        self.additions = self.additions.saturating_sub((count >> 2) as usize) >> 1;
+    }
+
+    /// Reallocate table for size, estimates of given hashes are copied to new table,
+    /// other counters start from zero. Counters are written directly, so copying
+    /// never triggers a reset.
+    pub fn resize(&mut self, size: usize, hashes: impl Iterator<Item = u64>) {
+        let mut sketch = CountMinSketch::new(size);
+        sketch.reset_floor = self.reset_floor;
+        sketch.resets = self.resets;
+        sketch.last_reset_additions = self.last_reset_additions;
+        let mut additions = 0;
+        for h in hashes {
+            let count = self.estimate(h) as u64;
+            if count == 0 {
+                continue;
+            }
+            for (index, offset) in sketch.counters(h) {
+                let offset = offset << 2;
+                let current = (sketch.table[index] >> offset) & 0xF;
+                if current < count {
+                    sketch.table[index] += (count - current) << offset;
+                }
+            }
+            additions += count as usize;
+        }
+        // next add past sample size ages copied counters as usual
+        sketch.additions = additions.min(sketch.sample_size - 1);
+        *self = sketch;
+    }
+
+    /// Zero all counters
+    pub fn clear(&mut self) {
+        self.table.iter_mut().for_each(|i| *i = 0);
+        self.additions = 0;
     }
 
     fn count(&self, h: u64, block: u64, offset: u8) -> usize {
@@ -95,6 +147,53 @@ impl CountMinSketch {
         *min
     }
 
+    fn counters(&self, h: u64) -> [(usize, usize); 4] {
+        let counter_hash = rehash(h);
+        let block = (h & (self.block_mask as u64)) << 3;
+        [
+            self.index_of(counter_hash, block, 0),
+            self.index_of(counter_hash, block, 1),
+            self.index_of(counter_hash, block, 2),
+            self.index_of(counter_hash, block, 3),
+        ]
+    }
+
+    /// True if two hashes share any of their four counters
+    pub fn collide(&self, a: u64, b: u64) -> bool {
+        let counters = self.counters(b);
+        self.counters(a).iter().any(|c| counters.contains(c))
+    }
+
+    /// All counters of hash reached 4 bit max, estimate is a lower bound
+    pub fn is_saturated(&self, h: u64) -> bool {
+        self.estimate(h) == 0xF
+    }
+
+    /// Number of resets since created, aging happens every sample size additions
+    pub fn resets(&self) -> u64 {
+        self.resets
+    }
+
+    /// Additions counted when the last reset ran, 0 if never reset
+    pub fn last_reset_additions(&self) -> usize {
+        self.last_reset_additions
+    }
+
+    /// Fraction of 4 bit counters at max, near 1.0 frequencies can't be told apart
+    pub fn saturation_ratio(&self) -> f64 {
+        let saturated: u32 = self
+            .table
+            .iter()
+            .map(|&val| (0..16).filter(|i| (val >> (i * 4)) & 0xF == 0xF).count() as u32)
+            .sum();
+        saturated as f64 / (self.table.len() * 16) as f64
+    }
+
+    #[cfg(test)]
+    pub fn width(&self) -> usize {
+        self.table.len()
+    }
+
     #[cfg(test)]
     fn table_counters(&self) -> Vec<Vec<i32>> {
         self.table
@@ -164,6 +263,81 @@ mod tests {
         assert!(failed < 40);
     }
 
+    #[test]
+    fn test_sketch_saturation_ratio() {
+        let mut sketch = CountMinSketch::new(100);
+        assert_eq!(sketch.saturation_ratio(), 0.0);
+        sketch.table[0] = u64::MAX;
+        sketch.table[1] = 0xF;
+        assert_eq!(
+            sketch.saturation_ratio(),
+            17.0 / (sketch.table.len() * 16) as f64
+        );
+        sketch.table.iter_mut().for_each(|i| *i = u64::MAX);
+        assert_eq!(sketch.saturation_ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_sketch_resize() {
+        let hasher = RandomState::with_seeds(9, 0, 7, 2);
+        let mut sketch = CountMinSketch::new(100);
+        let hashes: Vec<u64> = (0..50).map(|i| hasher.hash_one(i)).collect();
+        for (i, h) in hashes.iter().enumerate() {
+            for _ in 0..i % 10 {
+                sketch.add(*h);
+            }
+        }
+        let before: Vec<usize> = hashes.iter().map(|h| sketch.estimate(*h)).collect();
+        sketch.resize(10000, hashes.iter().copied());
+        assert_eq!(sketch.width(), 16384);
+        let after: Vec<usize> = hashes.iter().map(|h| sketch.estimate(*h)).collect();
+        // copied estimates can only gain from new collisions
+        for (b, a) in before.iter().zip(after.iter()) {
+            assert!(a >= b);
+        }
+        assert_eq!(sketch.estimate(hasher.hash_one("new")), 0);
+
+        // shrinking copies more than sample size additions without resetting
+        let hashes: Vec<u64> = (0..200).map(|i| hasher.hash_one(i)).collect();
+        for h in hashes.iter() {
+            for _ in 0..15 {
+                sketch.add(*h);
+            }
+        }
+        let resets = sketch.resets();
+        let before: Vec<usize> = hashes.iter().map(|h| sketch.estimate(*h)).collect();
+        sketch.resize(64, hashes.iter().copied());
+        assert_eq!(sketch.resets(), resets);
+        for (h, b) in hashes.iter().zip(before.iter()) {
+            assert!(sketch.estimate(*h) >= *b);
+        }
+        assert!(sketch.additions < sketch.sample_size);
+    }
+
+    #[test]
+    fn test_sketch_collide() {
+        let sketch = CountMinSketch::new(64);
+        let hasher = RandomState::with_seeds(9, 0, 7, 2);
+        let a = hasher.hash_one("a");
+        assert!(sketch.collide(a, a));
+        let mut collided = 0;
+        for i in 0..1000 {
+            let b = hasher.hash_one(format!("b:{}", i));
+            let mut sketch = CountMinSketch::new(64);
+            sketch.add(a);
+            for _ in 0..3 {
+                sketch.add(b);
+            }
+            if sketch.collide(a, b) {
+                collided += 1;
+            } else {
+                // disjoint counters, b can't inflate a
+                assert_eq!(sketch.estimate(a), 1);
+            }
+        }
+        assert!(collided > 0 && collided < 1000);
+    }
+
     #[test]
     fn test_sketch_reset_counter() {
         let mut sketch = CountMinSketch::new(1000);
@@ -184,6 +358,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sketch_reset_floor() {
+        let hasher = RandomState::with_seeds(9, 0, 7, 2);
+        let h = hasher.hash_one("foo");
+        let mut sketch = CountMinSketch::new(1000);
+        sketch.add(h);
+        sketch.reset();
+        assert_eq!(sketch.estimate(h), 0);
+
+        let mut sketch = CountMinSketch::new(1000);
+        sketch.reset_floor = true;
+        sketch.add(h);
+        sketch.reset();
+        assert_eq!(sketch.estimate(h), 1);
+        // other counters are halved as usual
+        sketch.additions = 100000;
+        for i in sketch.table.iter_mut() {
+            *i = 0x0123456789abcdef;
+        }
+        sketch.reset();
+        for i in sketch.table_counters().iter() {
+            assert_eq!(*i, vec![0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7]);
+        }
+    }
+
+    #[test]
+    fn test_sketch_resets() {
+        let hasher = RandomState::with_seeds(9, 0, 7, 2);
+        let mut sketch = CountMinSketch::new(100);
+        assert_eq!(sketch.resets(), 0);
+        assert_eq!(sketch.last_reset_additions(), 0);
+        let mut i = 0;
+        while sketch.resets() == 0 {
+            sketch.add(hasher.hash_one(format!("key:{}", i)));
+            i += 1;
+        }
+        assert_eq!(sketch.last_reset_additions(), sketch.sample_size);
+        assert!(sketch.additions < sketch.sample_size);
+        sketch.resize(200, std::iter::empty());
+        assert_eq!(sketch.resets(), 1);
+        sketch.reset();
+        assert_eq!(sketch.resets(), 2);
+        assert_eq!(sketch.last_reset_additions(), 0);
+    }
+
     #[test]
     fn test_sketch_reset_addition() {
         let mut sketch = CountMinSketch::new(500);
@@ -12,22 +12,33 @@ pub trait Cache {
 
 pub struct Clock {
     start: Instant,
+    // added to elapsed time, only moved by tests
+    offset: u128,
 }
 
 impl Clock {
     pub fn new() -> Self {
         Self {
             start: Instant::now(),
+            offset: 0,
         }
     }
 
     pub fn now_ns(&self) -> u128 {
-        (Instant::now() - self.start).as_nanos()
+        (Instant::now() - self.start).as_nanos() + self.offset
     }
 
+    /// Move clock forward, so tests can pass time without sleeping
+    #[cfg(test)]
+    pub fn skip(&mut self, duration: Duration) {
+        self.offset += duration.as_nanos();
+    }
+
+    /// Absolute expire time, saturates so huge ttl never wraps to near zero.
+    /// Saturated entries stay in top level bucket and effectively never expire.
     pub fn expire_ns(&self, ttl: u128) -> u128 {
         if ttl > 0 {
-            self.now_ns() + ttl
+            self.now_ns().saturating_add(ttl)
         } else {
             0
         }
@@ -41,6 +52,8 @@ pub struct TimerWheel {
     wheel: Vec<Vec<Link>>,
     pub clock: Clock,
     nanos: u128,
+    // last advanced time of each level, levels can be advanced separately
+    level_nanos: Vec<u128>,
 }
 
 impl TimerWheel {
@@ -90,10 +103,14 @@ impl TimerWheel {
             wheel,
             clock,
             nanos,
+            level_nanos: vec![nanos; 5],
         }
     }
 
+    // entry already due in wheel time, such as after advancing wheel ahead of the real
+    // clock, goes to the current level 0 bucket so next sweep removes it
     fn find_index(&self, expire: u128) -> (u8, u8) {
+        let expire = expire.max(self.nanos);
         let duration = expire - self.nanos;
         for i in 0..5 {
             if duration < self.spans[i + 1] {
@@ -105,8 +122,11 @@ impl TimerWheel {
         (4, 0)
     }
 
+    /// Entry with a corrupted wheel link is left where it is, see check_scheduled
     pub fn schedule(&mut self, index: u32, metadata: &mut MetaData) {
-        self.deschedule(index, metadata);
+        if !self.deschedule(index, metadata) {
+            return;
+        }
         let entry = &mut metadata.data[index as usize];
         if entry.expire > 0 {
             let w_index = self.find_index(entry.expire);
@@ -115,12 +135,32 @@ impl TimerWheel {
         }
     }
 
-    pub fn deschedule(&mut self, index: u32, metadata: &mut MetaData) {
+    /// Return false if entry's wheel link doesn't match its bucket, entry is unchanged
+    pub fn deschedule(&mut self, index: u32, metadata: &mut MetaData) -> bool {
         let entry = &mut metadata.data[index as usize];
         let w_index = entry.wheel_index;
         let link_id = entry.wheel_link_id;
         if link_id > 0 {
-            self.wheel[w_index.0 as usize][w_index.1 as usize].remove_wheel(index, metadata);
+            return self.wheel[w_index.0 as usize][w_index.1 as usize]
+                .remove_wheel(index, metadata);
+        }
+        true
+    }
+
+    /// Error if entry is scheduled but its wheel link doesn't match its bucket
+    pub fn check_scheduled(&self, index: u32, metadata: &MetaData) -> Result<(), &'static str> {
+        let entry = &metadata.data[index as usize];
+        let (level, slot) = entry.wheel_index;
+        if entry.wheel_link_id == 0 {
+            return Ok(());
+        }
+        match self
+            .wheel
+            .get(level as usize)
+            .and_then(|l| l.get(slot as usize))
+        {
+            Some(link) if link.id == entry.wheel_link_id => Ok(()),
+            _ => Err("wheel link id not match"),
         }
     }
 
@@ -131,52 +171,112 @@ impl TimerWheel {
         policy: &mut impl Policy,
         metadata: &mut MetaData,
     ) {
-        let previous = self.nanos;
-        self.nanos = now;
+        self.advance_limited(now, usize::MAX, cache, policy, metadata);
+    }
 
+    /// Same as advance, but stop after max_evictions removals. Buckets not fully
+    /// swept are kept, so next advance continues from them. Return removed count.
+    pub fn advance_limited(
+        &mut self,
+        now: u128,
+        max_evictions: usize,
+        cache: &mut impl Cache,
+        policy: &mut impl Policy,
+        metadata: &mut MetaData,
+    ) -> usize {
+        self.nanos = now;
+        let mut budget = max_evictions;
         for i in 0..5 {
-            let prev_ticks = previous >> self.shift[i];
-            let current_ticks = now >> self.shift[i];
-            if current_ticks <= prev_ticks {
-                break;
-            }
-            self.expire(
-                i,
-                prev_ticks,
-                current_ticks - prev_ticks,
-                cache,
-                policy,
-                metadata,
-            );
+            self.advance_one(i, now, &mut budget, cache, policy, metadata);
         }
+        max_evictions - budget
     }
 
+    /// Advance a single wheel level only, other levels keep their own progress
+    /// and catch up on their next advance
+    pub fn advance_level(
+        &mut self,
+        level: usize,
+        now: u128,
+        cache: &mut impl Cache,
+        policy: &mut impl Policy,
+        metadata: &mut MetaData,
+    ) -> Result<(), &'static str> {
+        if level >= self.wheel.len() {
+            return Err("level out of range");
+        }
+        self.nanos = self.nanos.max(now);
+        let mut budget = usize::MAX;
+        self.advance_one(level, now, &mut budget, cache, policy, metadata);
+        Ok(())
+    }
+
+    fn advance_one(
+        &mut self,
+        level: usize,
+        now: u128,
+        budget: &mut usize,
+        cache: &mut impl Cache,
+        policy: &mut impl Policy,
+        metadata: &mut MetaData,
+    ) {
+        let previous = self.level_nanos[level];
+        let prev_ticks = previous >> self.shift[level];
+        let current_ticks = now >> self.shift[level];
+        if current_ticks <= prev_ticks || *budget == 0 {
+            return;
+        }
+        let stopped = self.expire(
+            level,
+            prev_ticks,
+            current_ticks - prev_ticks,
+            budget,
+            cache,
+            policy,
+            metadata,
+        );
+        // resume from the partially swept bucket next time
+        self.level_nanos[level] = match stopped {
+            Some(tick) => tick << self.shift[level],
+            None => now,
+        };
+    }
+
+    // return tick of the bucket sweep stopped at if budget ran out
+    #[allow(clippy::too_many_arguments)]
     fn expire(
         &mut self,
         index: usize,
         prev_ticks: u128,
         delta: u128,
+        budget: &mut usize,
         cache: &mut impl Cache,
         policy: &mut impl Policy,
         metadata: &mut MetaData,
-    ) {
+    ) -> Option<u128> {
         let mask = (self.buckets[index] - 1) as u128;
         let steps = cmp::min(delta as usize, self.buckets[index]);
         let start = prev_ticks & mask;
         let end = start + steps as u128;
-        for i in start..end {
+        for (i, tick) in (start..end).zip(prev_ticks..) {
             let mut modified = Vec::new();
             let mut removed = Vec::new();
+            let mut exhausted = false;
 
             for (index, key, expire) in self.wheel[index][(i & mask) as usize].iter_wheel(metadata)
             {
                 if expire <= self.nanos {
+                    if removed.len() == *budget {
+                        exhausted = true;
+                        break;
+                    }
                     cache.del_item(key.as_str(), index);
                     removed.push(index);
                 } else {
                     modified.push(index);
                 }
             }
+            *budget -= removed.len();
 
             for index in removed.iter() {
                 self.deschedule(*index, metadata);
@@ -184,21 +284,95 @@ impl TimerWheel {
                 policy.remove(*index, metadata);
             }
 
-            // clear current bucket and reschedule items in current bucket
-            self.wheel[index][(i & mask) as usize].clear(metadata);
+            // rest of the bucket stays scheduled as is
+            if exhausted {
+                return Some(tick);
+            }
 
+            // reschedule items in current bucket, schedule unlinks each one first
             for index in modified.iter() {
                 self.schedule(*index, metadata)
             }
         }
+        None
     }
 
     pub fn clear(&mut self, metadata: &mut MetaData) {
         for i in self.wheel.iter_mut() {
             for j in i.iter_mut() {
-                j.clear(metadata)
+                j.clear(metadata);
+            }
+        }
+    }
+
+    /// Number of scheduled entries already expired at now but not removed yet,
+    /// walks every scheduled entry
+    pub fn expired_count(&self, now: u128, metadata: &MetaData) -> usize {
+        let mut count = 0;
+        for i in self.wheel.iter() {
+            for j in i.iter() {
+                count += j
+                    .iter_wheel(metadata)
+                    .filter(|(_, _, expire)| *expire <= now)
+                    .count();
+            }
+        }
+        count
+    }
+
+    /// Clock time of the last advance
+    pub fn current_nanos(&self) -> u64 {
+        self.nanos as u64
+    }
+
+    /// Span of each level in nanoseconds, a ttl shorter than span i + 1 is bucketed
+    /// in level i with span i granularity. Last level is repeated as its upper bound.
+    pub fn span_boundaries(&self) -> Vec<u64> {
+        self.spans.iter().map(|span| *span as u64).collect()
+    }
+
+    /// Clock time when the next level 0 bucket rolls over, advancing before it
+    /// expires nothing new
+    pub fn next_tick_nanos(&self) -> u64 {
+        (((self.nanos >> self.shift[0]) + 1) << self.shift[0]) as u64
+    }
+
+    /// Keys of scheduled entries already expired at now, entries are not removed
+    pub fn expired_keys(&self, now: u128, metadata: &MetaData) -> Vec<String> {
+        self.keys_where(metadata, |expire| expire <= now)
+    }
+
+    /// Keys of scheduled entries expiring in (from, to]
+    pub fn keys_expiring_between(&self, from: u128, to: u128, metadata: &MetaData) -> Vec<String> {
+        self.keys_where(metadata, |expire| expire > from && expire <= to)
+    }
+
+    // walk every scheduled entry, entries without ttl are never scheduled
+    fn keys_where(&self, metadata: &MetaData, filter: impl Fn(u128) -> bool) -> Vec<String> {
+        let mut keys = Vec::new();
+        for i in self.wheel.iter() {
+            for j in i.iter() {
+                keys.extend(
+                    j.iter_wheel(metadata)
+                        .filter(|(_, _, expire)| filter(*expire))
+                        .map(|(_, key, _)| key),
+                );
             }
         }
+        keys
+    }
+
+    /// Number of entries in top level catch-all bucket, TTL beyond ~6.5d
+    pub fn overflow_count(&self) -> usize {
+        self.wheel[4][0].len as usize
+    }
+
+    /// Number of entries currently scheduled in all buckets
+    pub fn len(&self) -> usize {
+        self.wheel
+            .iter()
+            .map(|i| i.iter().map(|j| j.len as usize).sum::<usize>())
+            .sum()
     }
 }
 
@@ -260,13 +434,20 @@ mod tests {
         let mut metadata = MetaData::new(1000);
         let mut tw = TimerWheel::new(1000, &mut metadata);
         let now = tw.clock.now_ns();
-        for (key, expire) in [("k1", 1u64), ("k2", 69u64), ("k3", 4399u64)] {
+        for (key, expire) in [
+            ("k1", 1u64),
+            ("k2", 69u64),
+            ("k3", 4399u64),
+            ("k4", 30 * 24 * 3600u64),
+        ] {
             let entry = metadata.get_or_create(key);
             entry.expire = now + Duration::from_secs(expire).as_nanos();
             let index = entry.index;
             tw.schedule(index, &mut metadata);
             assert!(metadata.data[index as usize].wheel_link_id > 0);
         }
+        assert_eq!(tw.len(), 4);
+        assert_eq!(tw.overflow_count(), 1);
 
         assert!(tw.wheel[0]
             .iter()
@@ -278,11 +459,13 @@ mod tests {
             .iter()
             .any(|x| x.iter_wheel(&metadata).any(|x| x.1 == "k3")));
         // deschedule test
-        for key in ["k1", "k2", "k3"] {
+        for key in ["k1", "k2", "k3", "k4"] {
             let index = metadata.get_or_create(key).index;
             tw.deschedule(index, &mut metadata);
             assert!(metadata.data[index as usize].wheel_link_id == 0);
         }
+        assert_eq!(tw.len(), 0);
+        assert_eq!(tw.overflow_count(), 0);
         assert!(!tw.wheel[0]
             .iter()
             .any(|x| x.iter_wheel(&metadata).any(|x| x.1 == "k1")));
@@ -327,6 +510,15 @@ mod tests {
         );
         assert_eq!(cache.deleted.len(), 3);
         assert_eq!(policy.len(), 4);
+        assert_eq!(tw.len(), 4);
+        assert_eq!(
+            tw.expired_count(now + Duration::from_secs(200).as_nanos(), &metadata),
+            1
+        );
+        assert_eq!(
+            tw.expired_keys(now + Duration::from_secs(200).as_nanos(), &metadata),
+            ["k4"]
+        );
         for key in ["k1", "k2", "k3"] {
             let index = metadata.get_or_create(key).index;
             assert!(metadata.data[index as usize].wheel_link_id == 0);
@@ -376,6 +568,7 @@ mod tests {
         );
         assert_eq!(cache.deleted.len(), 6);
         assert_eq!(policy.len(), 1);
+        assert_eq!(tw.len(), 1);
         for key in ["k1", "k2", "k3", "k4", "k5", "k6"] {
             let index = metadata.get_or_create(key).index;
             assert!(metadata.data[index as usize].wheel_link_id == 0);
@@ -400,6 +593,138 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_advance_huge_ttl() {
+        let mut core = TlfuCore::new(1000);
+        let now = core.wheel.clock.now_ns();
+        let cache = &mut MockCache {
+            deleted: Vec::new(),
+        };
+        let centuries = Duration::from_secs(500 * 365 * 24 * 3600).as_nanos();
+        core.set("k1", centuries);
+        core.set("k2", u128::MAX);
+        core.set("k3", u64::MAX as u128);
+        for key in ["k1", "k2", "k3"] {
+            let index = core.metadata.get(key).unwrap();
+            assert!(core.metadata.data[index as usize].expire > now + centuries / 2);
+        }
+        assert_eq!(core.wheel.overflow_count(), 3);
+        for days in [1, 7, 30, 365] {
+            core.wheel.advance(
+                now + Duration::from_secs(days * 24 * 3600).as_nanos(),
+                cache,
+                &mut core.policy,
+                &mut core.metadata,
+            );
+            assert!(cache.deleted.is_empty());
+            assert_eq!(core.len(), 3);
+            assert_eq!(core.wheel.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_advance_level() {
+        let mut core = TlfuCore::new(1000);
+        let now = core.wheel.clock.now_ns();
+        let cache = &mut MockCache {
+            deleted: Vec::new(),
+        };
+        core.set("k1", now + Duration::from_secs(1).as_nanos());
+        core.set("k2", now + Duration::from_secs(120).as_nanos());
+        let later = now + Duration::from_secs(300).as_nanos();
+        assert!(core
+            .wheel
+            .advance_level(5, later, cache, &mut core.policy, &mut core.metadata)
+            .is_err());
+        // level 0 only, k2 on level 1 is untouched
+        core.wheel
+            .advance_level(0, later, cache, &mut core.policy, &mut core.metadata)
+            .unwrap();
+        assert_eq!(cache.deleted, vec!["k1".to_string()]);
+        assert_eq!(core.wheel.len(), 1);
+        // level 1 catches up
+        core.wheel
+            .advance_level(1, later, cache, &mut core.policy, &mut core.metadata)
+            .unwrap();
+        assert_eq!(cache.deleted.len(), 2);
+        assert_eq!(core.wheel.len(), 0);
+        assert_eq!(core.len(), 0);
+    }
+
+    #[test]
+    fn test_advance_limited() {
+        let mut core = TlfuCore::new(1000);
+        let now = core.wheel.clock.now_ns();
+        let cache = &mut MockCache {
+            deleted: Vec::new(),
+        };
+        for i in 0..10 {
+            core.set(&format!("a:{}", i), Duration::from_secs(1).as_nanos());
+        }
+        for i in 0..3 {
+            core.set(
+                &format!("b:{}", i),
+                Duration::from_secs(10 * (i + 2)).as_nanos(),
+            );
+        }
+        let later = now + Duration::from_secs(60).as_nanos();
+        for removed in [4, 4, 4, 1, 0] {
+            assert_eq!(
+                core.wheel
+                    .advance_limited(later, 4, cache, &mut core.policy, &mut core.metadata),
+                removed
+            );
+            assert_eq!(cache.deleted.len(), core.wheel.len().abs_diff(13));
+        }
+        assert_eq!(core.wheel.len(), 0);
+        assert_eq!(core.len(), 0);
+        assert!(core.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_span_boundaries() {
+        let mut metadata = MetaData::new(1000);
+        let tw = TimerWheel::new(1000, &mut metadata);
+        let spans = tw.span_boundaries();
+        assert_eq!(spans.len(), 6);
+        assert!(spans[1] > Duration::from_secs(68).as_nanos() as u64);
+        assert!(spans[1] < Duration::from_secs(69).as_nanos() as u64);
+        for i in 0..4 {
+            let below = tw.find_index(tw.nanos + spans[i + 1] as u128 - 1);
+            assert_eq!(below.0 as usize, i);
+            let at = tw.find_index(tw.nanos + spans[i + 1] as u128);
+            assert_eq!(at.0 as usize, i + 1);
+        }
+    }
+
+    #[test]
+    fn test_next_tick() {
+        let mut core = TlfuCore::new(1000);
+        let now = core.wheel.clock.now_ns();
+        let cache = &mut MockCache {
+            deleted: Vec::new(),
+        };
+        core.set("k1", 1);
+        core.wheel
+            .advance(now, cache, &mut core.policy, &mut core.metadata);
+        assert_eq!(core.wheel.current_nanos(), now as u64);
+        let next = core.wheel.next_tick_nanos();
+        assert!(next > now as u64);
+        assert!(next - now as u64 <= 1 << core.wheel.shift[0]);
+        assert_eq!(next % (1 << core.wheel.shift[0]), 0);
+        // k1 stays until level 0 ticks
+        core.wheel.advance(
+            next as u128 - 1,
+            cache,
+            &mut core.policy,
+            &mut core.metadata,
+        );
+        assert!(cache.deleted.is_empty());
+        core.wheel
+            .advance(next as u128, cache, &mut core.policy, &mut core.metadata);
+        assert_eq!(cache.deleted, ["k1"]);
+    }
+
     // Simple no panic test
     #[test]
     fn test_advance_large() {
@@ -1,33 +1,59 @@
+use crate::filter::BloomFilter;
 use crate::lru::{Lru, Slru};
 use crate::metadata::MetaData;
 use crate::policy::Policy;
 use crate::sketch::CountMinSketch;
 use crate::timerwheel::Clock;
 use ahash::RandomState;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::BinaryHeap;
 
 pub struct TinyLfu {
     size: usize,
     lru: Lru,
     slru: Slru,
     pub sketch: CountMinSketch,
-    hasher: RandomState,
+    pub hasher: RandomState,
     lru_factor: usize,
     total: usize,
     hit: usize,
     hr: f32,
     step: i8,
+    pub admit_ties: bool,
+    pub rng: StdRng,
+    pub admit_wins: u64,
+    pub admit_losses: u64,
+    no_window: bool,
+    // stop hill climbing, lru factor keeps current value
+    pub freeze_climber: bool,
+    // add keys not in cache to sketch on access, so a key is warm by the time it is set
+    pub record_misses: bool,
+    // (candidate_freq, victim_freq, random_roll, admitted, candidate_from_window) of
+    // each admit call, if enabled
+    pub admit_trace: Option<Vec<AdmitRecord>>,
+    // window entry moved to probation by the last set, still resident
+    pub migrated: Option<u32>,
+    // losing candidate above hashdos threshold is admitted with 1 in this chance, 0 (default)
+    // disables
+    random_admit_one_in: u32,
+    // skip admission contest, window candidate always replaces victim
+    pub recency_only: bool,
+    // keys known to be hot from elsewhere, candidates found here get a frequency bonus
+    pub admission_prior: Option<BloomFilter>,
 }
 
+pub type AdmitRecord = (usize, usize, bool, bool, bool);
+
+// frequency added to a candidate present in admission prior
+const ADMISSION_PRIOR_BONUS: usize = 2;
+// losing candidates at least this frequent are admitted at random, so an attacker
+// can't pin a victim by colliding its hash with hot keys
+const ADMIT_HASHDOS_THRESHOLD: usize = 6;
+
 impl Policy for TinyLfu {
-    // remove key
+    // remove key, entry with unknown link id is not in any list so there is nothing to unlink
     fn remove(&mut self, index: u32, metadata: &mut MetaData) {
-        let entry = &mut metadata.data[index as usize];
-        match entry.link_id {
-            0 => (),
-            1 => self.lru.remove(index, metadata),
-            2 | 3 => self.slru.remove(index, metadata),
-            _ => unreachable!(),
-        };
+        let _ = self.try_remove(index, metadata);
     }
 }
 
@@ -49,13 +75,56 @@ impl TinyLfu {
             hit: 0,   // hit since last climbing
             hr: 0.0,  // last hit ratio
             step: 1,
+            admit_ties: false,
+            rng: StdRng::from_entropy(),
+            admit_wins: 0,
+            admit_losses: 0,
+            no_window: false,
+            freeze_climber: false,
+            record_misses: true,
+            admit_trace: None,
+            migrated: None,
+            random_admit_one_in: 0,
+            recency_only: false,
+            admission_prior: None,
+        }
+    }
+
+    /// Use exact window, probation and protected capacities instead of the default
+    /// 1% window and 80% protected split, policy must be empty and sized to their sum.
+    /// Zero window disables the window.
+    pub fn set_layout(
+        &mut self,
+        window: usize,
+        probation: usize,
+        protected: usize,
+        metadata: &mut MetaData,
+    ) {
+        self.size = window + probation + protected;
+        if window == 0 {
+            self.disable_window();
+        } else {
+            self.lru.link.capacity = window as u32;
+            self.slru.set_maxsize(probation + protected);
         }
+        self.slru
+            .set_protected_capacity(protected, usize::MAX, metadata);
     }
 
     // add/update key
     pub fn set(&mut self, index: u32, metadata: &mut MetaData) -> Option<u32> {
-        // hill climbing lru factor
-        if self.total >= 10 * self.size && (self.total - self.hit) > self.size / 2 {
+        self.set_at(index, 0, metadata)
+    }
+
+    /// Same as set, entries expired at now are evicted first without an admission
+    /// contest. Now 0 never treats entries as expired.
+    pub fn set_at(&mut self, index: u32, now: u128, metadata: &mut MetaData) -> Option<u32> {
+        // hill climbing lru factor, frozen without window
+        if !self.no_window
+            && !self.freeze_climber
+            && self.total >= 10 * self.size
+            && (self.total - self.hit) > self.size / 2
+        {
             let current = self.hit as f32 / self.total as f32;
             let delta = current - self.hr;
             if delta > 0.0 {
@@ -82,48 +151,342 @@ impl TinyLfu {
             self.total = 0;
         }
 
+        self.migrated = None;
         let entry = &mut metadata.data[index as usize];
+        // new entry goes to probation directly if window is disabled
+        if entry.link_id == 0 && self.no_window {
+            if !self.slru.probation.rotate_pinned(metadata) && self.slru.victim(metadata).is_some()
+            {
+                return Some(index);
+            }
+            return self.slru.insert(index, metadata);
+        }
         // new entry
         if entry.link_id == 0 {
-            if let Some(evicted) = self.lru.insert(index, metadata) {
-                if let Some(victim) = self.slru.victim(metadata) {
+            let evicted = if self.lru.len() >= self.lru.capacity()
+                && !self.lru.link.rotate_pinned(metadata)
+            {
+                // window is all pinned, new entry competes for main directly
+                Some(index)
+            } else {
+                self.lru.insert(index, metadata)
+            };
+            if let Some(evicted) = evicted {
+                // expired window candidate is dead, drop it instead of a live victim
+                if evicted != index && Self::is_expired(evicted, now, metadata) {
+                    return Some(evicted);
+                }
+                // every probation entry is pinned, nothing can make room
+                if !self.slru.probation.rotate_pinned(metadata)
+                    && self.slru.victim(metadata).is_some()
+                {
+                    return Some(evicted);
+                }
+                if let Some(victim) = self
+                    .slru
+                    .victim(metadata)
+                    .filter(|victim| !Self::is_expired(*victim, now, metadata))
+                {
                     let ekey = metadata.data[evicted as usize].key.to_string();
                     let vkey = metadata.data[victim as usize].key.to_string();
-                    let evicted_count =
-                        self.sketch.estimate(self.hasher.hash_one(ekey)) + self.lru_factor;
-                    let victim_count = self.sketch.estimate(self.hasher.hash_one(vkey));
-                    if evicted_count <= victim_count {
+                    // window is skipped if it is all pinned, new entry contests directly
+                    if !self.admit_from(&ekey, &vkey, evicted != index) {
+                        self.admit_losses += 1;
                         return Some(evicted);
                     }
+                    self.admit_wins += 1;
                 }
                 // reinsert evicted one from lru to slru
-                if let Some(evicted_new) = self.slru.insert(evicted, metadata) {
-                    return Some(evicted_new);
+                let evicted_new = self.slru.insert(evicted, metadata);
+                if evicted_new != Some(evicted) && evicted != index {
+                    self.migrated = Some(evicted);
+                }
+                if evicted_new.is_some() {
+                    return evicted_new;
                 }
             }
         }
         None
     }
 
-    /// Mark access, update sketch and lru/slru
-    pub fn access(&mut self, key: &str, clock: &Clock, metadata: &mut MetaData) -> Option<u32> {
-        self.sketch.add(self.hasher.hash_one(key.to_string()));
+    fn is_expired(index: u32, now: u128, metadata: &MetaData) -> bool {
+        let expire = metadata.data[index as usize].expire;
+        expire != 0 && expire <= now
+    }
+
+    /// Remove admission window, policy becomes a plain slru of full size.
+    /// Must be called before any entry is added.
+    pub fn disable_window(&mut self) {
+        self.no_window = true;
+        self.lru.link.capacity = 0;
+        self.slru.set_maxsize(self.size);
+    }
+
+    /// Admission contest, return true if candidate should replace victim
+    pub fn admit(&mut self, candidate: &str, victim: &str) -> bool {
+        self.admit_from(candidate, victim, false)
+    }
+
+    /// Same as admit, from_window marks a candidate evicted from window, recorded in
+    /// admit trace to tell fresh window evictions from other contests
+    pub fn admit_from(&mut self, candidate: &str, victim: &str, from_window: bool) -> bool {
+        if self.recency_only {
+            return true;
+        }
+        let (candidate_freq, candidate_count, victim_count) = self.contest(candidate, victim);
+        let mut admitted = self.wins(candidate_count, victim_count);
+        let mut random_roll = false;
+        // threshold is on sketch frequency only, bonuses don't make a cold key frequent
+        if !admitted && candidate_freq >= ADMIT_HASHDOS_THRESHOLD && self.random_admit_one_in > 0 {
+            random_roll = true;
+            admitted = self.rng.gen_range(0..self.random_admit_one_in) == 0;
+        }
+        if let Some(trace) = self.admit_trace.as_mut() {
+            trace.push((
+                candidate_count,
+                victim_count,
+                random_roll,
+                admitted,
+                from_window,
+            ));
+        }
+        admitted
+    }
+
+    /// Same contest as admit without random admission, trace or any other state change,
+    /// for checking if a key would be admitted before inserting it
+    pub fn would_admit(&self, candidate: &str, victim: &str) -> bool {
+        if self.recency_only {
+            return true;
+        }
+        let (_, candidate_count, victim_count) = self.contest(candidate, victim);
+        self.wins(candidate_count, victim_count)
+    }
+
+    // (candidate sketch frequency, candidate count with bonuses, victim count)
+    fn contest(&self, candidate: &str, victim: &str) -> (usize, usize, usize) {
+        let candidate_hash = self.hasher.hash_one(candidate);
+        let candidate_freq = self.sketch.estimate(candidate_hash);
+        let mut candidate_count = candidate_freq + self.lru_factor;
+        if let Some(prior) = self.admission_prior.as_ref() {
+            // prior is filled with put_hash of key hashes, not the filter's own hasher
+            if prior.contains_hash(candidate_hash) {
+                candidate_count += ADMISSION_PRIOR_BONUS;
+            }
+        }
+        let victim_count = self.sketch.estimate(self.hasher.hash_one(victim));
+        (candidate_freq, candidate_count, victim_count)
+    }
+
+    fn wins(&self, candidate_count: usize, victim_count: usize) -> bool {
+        if self.admit_ties {
+            candidate_count >= victim_count
+        } else {
+            candidate_count > victim_count
+        }
+    }
+
+    /// Losing candidates at least as frequent as the hashdos threshold are admitted with
+    /// probability 1 / one_in, default 0 (disabled).
+    pub fn set_random_admit_rate(&mut self, one_in: u32) {
+        self.random_admit_one_in = one_in;
+    }
+
+    pub fn random_admit_rate(&self) -> u32 {
+        self.random_admit_one_in
+    }
+
+    /// Entry that will be evicted next if policy is full
+    pub fn victim(&mut self, metadata: &mut MetaData) -> Option<u32> {
+        self.slru.probation.rotate_pinned(metadata);
+        if let Some(victim) = self.slru.victim(metadata) {
+            return Some(victim);
+        }
+        self.lru.link.tail(metadata)
+    }
+
+    /// Remove key from lru/slru, return error instead of panic if link id is corrupted
+    pub fn try_remove(&mut self, index: u32, metadata: &mut MetaData) -> Result<(), &'static str> {
+        let entry = &mut metadata.data[index as usize];
+        match entry.link_id {
+            0 => Ok(()),
+            1 => {
+                self.lru.remove(index, metadata);
+                Ok(())
+            }
+            2 | 3 => self.slru.remove(index, metadata),
+            _ => Err("link id not match"),
+        }
+    }
+
+    /// Mark access, update sketch and lru/slru.
+    /// Return error instead of panic if link id is corrupted.
+    pub fn access(
+        &mut self,
+        key: &str,
+        clock: &Clock,
+        metadata: &mut MetaData,
+    ) -> Result<Option<u32>, &'static str> {
+        let index = metadata.get(key);
+        // expired entry waiting for sweep is dead and pending entry has no value yet,
+        // don't let them gain frequency
+        let expired = index.is_some_and(|index| {
+            let entry = &metadata.data[index as usize];
+            entry.pending || (entry.expire != 0 && entry.expire <= clock.now_ns())
+        });
+        if !expired && (index.is_some() || self.record_misses) {
+            self.sketch.add(self.hasher.hash_one(key.to_string()));
+        }
         self.total += 1;
-        if let Some(index) = metadata.get(key) {
+        if let Some(index) = index {
             self.hit += 1;
-            let entry = &metadata.data[index as usize];
-            if entry.expire != 0 && entry.expire <= clock.now_ns() {
-                return None;
+            if expired {
+                return Ok(None);
             }
             let link_id = metadata.data[index as usize].link_id;
             match link_id {
                 1 => self.lru.access(index, metadata),
-                2 | 3 => self.slru.access(index, metadata),
-                _ => unreachable!(),
+                2 | 3 => self.slru.access(index, metadata)?,
+                _ => return Err("link id not match"),
             }
-            return Some(index);
+            return Ok(Some(index));
         }
-        None
+        Ok(None)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+
+    /// Resize protected segment of slru, at most max_demotions overflow entries are
+    /// demoted to probation, the rest wait for demote_protected. Probation hits are not
+    /// promoted meanwhile. Hill climbing only tunes admission factor, so manual value is kept.
+    pub fn set_protected_capacity(
+        &mut self,
+        capacity: usize,
+        max_demotions: usize,
+        metadata: &mut MetaData,
+    ) -> usize {
+        self.slru
+            .set_protected_capacity(capacity, max_demotions, metadata)
+    }
+
+    /// Continue deferred demotions, return number demoted
+    pub fn demote_protected(&mut self, max: usize, metadata: &mut MetaData) -> usize {
+        self.slru.demote(max, metadata)
+    }
+
+    /// Entries moved from protected to probation since created
+    pub fn demotions(&self) -> u64 {
+        self.slru.demotions
+    }
+
+    /// Protected entries over capacity waiting for demotion
+    pub fn pending_demotions(&self) -> usize {
+        self.slru
+            .protected_len()
+            .saturating_sub(self.slru.protected_capacity())
+    }
+
+    /// Empty window and slru. Sketch and hill climbing state are reset too
+    /// unless preserve_sketch is true.
+    pub fn clear(&mut self, metadata: &mut MetaData, preserve_sketch: bool) {
+        self.lru.link.clear(metadata);
+        self.slru.clear(metadata);
+        if !preserve_sketch {
+            self.sketch.clear();
+            self.reset_adaptation();
+        }
+    }
+
+    /// Restore hill climbing state to constructor defaults, entries are kept.
+    /// Window size is fixed so there is no capacity to rebalance.
+    pub fn reset_adaptation(&mut self) {
+        self.lru_factor = 0;
+        self.total = 0;
+        self.hit = 0;
+        self.hr = 0.0;
+        self.step = 1;
+    }
+
+    /// Sample up to k distinct entries, weight falls linearly from front to tail
+    /// of each segment so recently used entries are more likely. Walks all entries.
+    pub fn sample(&mut self, k: usize, metadata: &MetaData) -> Vec<u32> {
+        if k == 0 {
+            return Vec::new();
+        }
+        // weighted reservoir sampling, keep k smallest -ln(u) / weight. Bits of
+        // non-negative floats sort the same as the floats.
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for link in [&self.lru.link, &self.slru.probation, &self.slru.protected] {
+            let len = link.len as f64;
+            for (rank, index) in link.iter(metadata).enumerate() {
+                let weight = (len - rank as f64) / len;
+                let u: f64 = 1.0 - self.rng.gen::<f64>();
+                heap.push(((-u.ln() / weight).to_bits(), index));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+        }
+        heap.into_sorted_vec().into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// Keys of window, probation and protected from front to tail
+    pub fn dump_order(&self, metadata: &MetaData) -> Vec<Vec<String>> {
+        vec![
+            self.lru.link.keys(metadata),
+            self.slru.probation.keys(metadata),
+            self.slru.protected.keys(metadata),
+        ]
+    }
+
+    /// Verify links are well formed and segments are within capacity
+    pub fn check_invariants(&self, metadata: &MetaData) -> Result<(), String> {
+        self.lru.link.check(metadata)?;
+        self.slru.probation.check(metadata)?;
+        self.slru.protected.check(metadata)?;
+        if self.lru.len() > self.lru.capacity() {
+            return Err(format!(
+                "window len {} over capacity {}",
+                self.lru.len(),
+                self.lru.capacity()
+            ));
+        }
+        // protected can stay over capacity while demotions are deferred
+        // probation capacity is the whole slru size
+        let slru_len = self.slru.probation_len() + self.slru.protected_len();
+        if slru_len > self.slru.probation_capacity() {
+            return Err(format!(
+                "slru len {} over capacity {}",
+                slru_len,
+                self.slru.probation_capacity()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Length of (window lru, slru probation, slru protected)
+    pub fn segment_lens(&self) -> (usize, usize, usize) {
+        (
+            self.lru.len(),
+            self.slru.probation_len(),
+            self.slru.protected_len(),
+        )
+    }
+
+    /// Window capacity over total capacity. Hill climbing tunes lru factor, not
+    /// window size, so this only changes with layout.
+    pub fn window_ratio(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        self.lru.capacity() as f64 / self.size as f64
+    }
+
+    /// Admission bonus for window candidates, tuned by hill climbing
+    pub fn lru_factor(&self) -> usize {
+        self.lru_factor
     }
 
     /// Current length of policy(lru + slru)
@@ -137,7 +500,9 @@ mod tests {
     use crate::{metadata::MetaData, timerwheel::Clock};
 
     use super::TinyLfu;
+    use crate::filter::BloomFilter;
     use crate::policy::Policy;
+    use rand::{rngs::StdRng, SeedableRng};
 
     fn key_to_index(key: &str, metadata: &mut MetaData) -> u32 {
         metadata.get_or_create(key).index
@@ -175,7 +540,7 @@ mod tests {
         assert_eq!(tlfu.slru.protected_len(), 0);
 
         // access same key will move the key from probation to protected
-        tlfu.access("key:10", &clock, &mut metadata);
+        tlfu.access("key:10", &clock, &mut metadata).unwrap();
         assert_eq!(tlfu.lru.len(), 10);
         assert_eq!(tlfu.slru.probation_len(), 189);
         assert_eq!(tlfu.slru.protected_len(), 1);
@@ -188,7 +553,7 @@ mod tests {
             tlfu.lru.link.display(false, &metadata)
         );
         // access again, length should be same
-        tlfu.access("key:10", &clock, &mut metadata);
+        tlfu.access("key:10", &clock, &mut metadata).unwrap();
         assert_eq!(tlfu.lru.len(), 10);
         assert_eq!(tlfu.slru.probation_len(), 189);
         assert_eq!(tlfu.slru.protected_len(), 1);
@@ -213,10 +578,10 @@ mod tests {
         // test estimate
         let victim = tlfu.slru.victim(&mut metadata);
         assert_eq!(victim.unwrap(), metadata.get("key:0").unwrap());
-        tlfu.access("key:991", &clock, &mut metadata);
-        tlfu.access("key:991", &clock, &mut metadata);
-        tlfu.access("key:991", &clock, &mut metadata);
-        tlfu.access("key:991", &clock, &mut metadata);
+        tlfu.access("key:991", &clock, &mut metadata).unwrap();
+        tlfu.access("key:991", &clock, &mut metadata).unwrap();
+        tlfu.access("key:991", &clock, &mut metadata).unwrap();
+        tlfu.access("key:991", &clock, &mut metadata).unwrap();
         let evicted = tlfu.set(key_to_index("key:1a", &mut metadata), &mut metadata);
         assert_eq!(evicted.unwrap(), metadata.get("key:992").unwrap());
         assert_eq!(tlfu.slru.probation_len(), 989);
@@ -261,6 +626,459 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tlfu_admit_ties() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        for key in ["a", "b"] {
+            tlfu.access(key, &clock, &mut metadata).unwrap();
+            tlfu.access(key, &clock, &mut metadata).unwrap();
+        }
+        assert!(!tlfu.admit("a", "b"));
+        tlfu.admit_ties = true;
+        assert!(tlfu.admit("a", "b"));
+        tlfu.access("b", &clock, &mut metadata).unwrap();
+        assert!(!tlfu.admit("a", "b"));
+    }
+
+    #[test]
+    fn test_tlfu_set_evict_expired() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(10, &mut metadata);
+        for i in 0..10 {
+            let index = key_to_index(&format!("key:{}", i), &mut metadata);
+            assert!(tlfu.set_at(index, 10, &mut metadata).is_none());
+        }
+        // probation tail is hot but expired, new key wins without contest
+        let victim = tlfu.slru.victim(&mut metadata).unwrap();
+        let h = tlfu
+            .hasher
+            .hash_one(metadata.data[victim as usize].key.as_str());
+        for _ in 0..5 {
+            tlfu.sketch.add(h);
+        }
+        metadata.data[victim as usize].expire = 5;
+        let losses = tlfu.admit_losses;
+        let index = key_to_index("new:0", &mut metadata);
+        assert_eq!(tlfu.set_at(index, 10, &mut metadata), Some(victim));
+        assert_eq!(tlfu.admit_losses, losses);
+        // without now, same setup loses the contest
+        let victim = tlfu.slru.victim(&mut metadata).unwrap();
+        let h = tlfu
+            .hasher
+            .hash_one(metadata.data[victim as usize].key.as_str());
+        for _ in 0..5 {
+            tlfu.sketch.add(h);
+        }
+        metadata.data[victim as usize].expire = 5;
+        let window = tlfu.lru.link.tail(&metadata).unwrap();
+        let index = key_to_index("new:1", &mut metadata);
+        assert_eq!(tlfu.set(index, &mut metadata), Some(window));
+        assert_eq!(tlfu.admit_losses, losses + 1);
+        // expired window candidate is dropped before the contest
+        let window = tlfu.lru.link.tail(&metadata).unwrap();
+        metadata.data[window as usize].expire = 5;
+        let index = key_to_index("new:2", &mut metadata);
+        assert_eq!(tlfu.set_at(index, 10, &mut metadata), Some(window));
+        assert_eq!(tlfu.admit_losses, losses + 1);
+    }
+
+    #[test]
+    fn test_tlfu_random_admit_rate() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        tlfu.rng = StdRng::seed_from_u64(7);
+        for _ in 0..8 {
+            tlfu.access("a", &clock, &mut metadata).unwrap();
+        }
+        for _ in 0..12 {
+            tlfu.access("b", &clock, &mut metadata).unwrap();
+        }
+        // disabled by default
+        assert_eq!(tlfu.random_admit_rate(), 0);
+        assert!(!(0..1000).any(|_| tlfu.admit("a", "b")));
+        for one_in in [8u32, 32, 128, 256] {
+            tlfu.set_random_admit_rate(one_in);
+            let admitted = (0..25600).filter(|_| tlfu.admit("a", "b")).count();
+            let expected = 25600 / one_in as usize;
+            assert!(admitted > expected * 3 / 4 && admitted < expected * 5 / 4);
+        }
+        tlfu.set_random_admit_rate(0);
+        assert!(!(0..1000).any(|_| tlfu.admit("a", "b")));
+        // candidate below threshold never rolls
+        tlfu.set_random_admit_rate(1);
+        tlfu.admit_trace = Some(Vec::new());
+        assert!(!tlfu.admit("c", "b"));
+        assert!(tlfu.admit("a", "b"));
+        let trace = tlfu.admit_trace.unwrap();
+        assert!(!trace[0].2);
+        assert!(trace[1].2);
+        // admission bonus doesn't count toward threshold
+        tlfu.lru_factor = 10;
+        tlfu.admit_trace = Some(Vec::new());
+        tlfu.admit("c", "b");
+        assert!(!tlfu.admit_trace.unwrap()[0].2);
+    }
+
+    #[test]
+    fn test_tlfu_recency_only() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        tlfu.access("b", &clock, &mut metadata).unwrap();
+        assert!(!tlfu.admit("a", "b"));
+        tlfu.recency_only = true;
+        tlfu.admit_trace = Some(Vec::new());
+        assert!(tlfu.admit("a", "b"));
+        assert!(tlfu.admit_trace.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tlfu_admission_prior() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        tlfu.access("b", &clock, &mut metadata).unwrap();
+        assert!(!tlfu.admit("a", "b"));
+        let mut prior = BloomFilter::new(100, 0.001).unwrap();
+        prior.put_hash(tlfu.hasher.hash_one("a"));
+        // filter's own string hashing is not used
+        prior.put("c");
+        tlfu.admission_prior = Some(prior);
+        assert!(tlfu.admit("a", "b"));
+        assert!(!tlfu.admit("c", "b"));
+        tlfu.admission_prior = None;
+        assert!(!tlfu.admit("a", "b"));
+    }
+
+    #[test]
+    fn test_tlfu_set_protected_capacity() {
+        let mut metadata = MetaData::new(1000);
+        let mut tlfu = TinyLfu::new(1000, &mut metadata);
+        let clock = Clock::new();
+        for i in 0..1000 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        for i in 0..100 {
+            tlfu.access(&format!("key:{}", i), &clock, &mut metadata)
+                .unwrap();
+        }
+        assert_eq!(tlfu.slru.protected_len(), 100);
+        assert_eq!(tlfu.slru.probation_len(), 890);
+        assert_eq!(
+            tlfu.set_protected_capacity(10, usize::MAX, &mut metadata),
+            90
+        );
+        assert_eq!(tlfu.slru.demotions, 90);
+        assert_eq!(tlfu.slru.protected_capacity(), 10);
+        assert_eq!(tlfu.slru.protected_len(), 10);
+        assert_eq!(tlfu.slru.probation_len(), 980);
+        // most recently accessed keys stay protected
+        let index = metadata.get("key:99").unwrap();
+        assert_eq!(metadata.data[index as usize].link_id, 3);
+        let index = metadata.get("key:0").unwrap();
+        assert_eq!(metadata.data[index as usize].link_id, 2);
+        tlfu.access("key:500", &clock, &mut metadata).unwrap();
+        assert_eq!(tlfu.slru.protected_len(), 10);
+        assert_eq!(tlfu.len(), 1000);
+    }
+
+    #[test]
+    fn test_tlfu_deferred_demotions() {
+        let mut metadata = MetaData::new(1000);
+        let mut tlfu = TinyLfu::new(1000, &mut metadata);
+        let clock = Clock::new();
+        for i in 0..1000 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        for i in 0..100 {
+            tlfu.access(&format!("key:{}", i), &clock, &mut metadata)
+                .unwrap();
+        }
+        assert_eq!(tlfu.set_protected_capacity(10, 30, &mut metadata), 30);
+        assert_eq!(tlfu.slru.protected_len(), 70);
+        assert_eq!(tlfu.pending_demotions(), 60);
+        assert!(tlfu.check_invariants(&metadata).is_ok());
+        // probation hit is not promoted while demotions are pending
+        tlfu.access("key:500", &clock, &mut metadata).unwrap();
+        let index = metadata.get("key:500").unwrap();
+        assert_eq!(metadata.data[index as usize].link_id, 2);
+        assert_eq!(tlfu.demote_protected(50, &mut metadata), 50);
+        assert_eq!(tlfu.demote_protected(50, &mut metadata), 10);
+        assert_eq!(tlfu.pending_demotions(), 0);
+        assert_eq!(tlfu.slru.demotions, 90);
+        tlfu.access("key:500", &clock, &mut metadata).unwrap();
+        assert_eq!(metadata.data[index as usize].link_id, 3);
+        assert_eq!(tlfu.slru.protected_len(), 10);
+        // promotion into full protected demotes its tail
+        assert_eq!(tlfu.slru.demotions, 91);
+        assert_eq!(tlfu.len(), 1000);
+    }
+
+    #[test]
+    fn test_tlfu_protected_hit_with_pending_demotions() {
+        let mut metadata = MetaData::new(1000);
+        let mut tlfu = TinyLfu::new(1000, &mut metadata);
+        let clock = Clock::new();
+        for i in 0..1000 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        for i in 0..100 {
+            tlfu.access(&format!("key:{}", i), &clock, &mut metadata)
+                .unwrap();
+        }
+        // one over capacity, hit must not drop protected tail
+        assert_eq!(tlfu.set_protected_capacity(99, 0, &mut metadata), 0);
+        tlfu.access("key:50", &clock, &mut metadata).unwrap();
+        assert_eq!(tlfu.slru.protected_len(), 100);
+        assert!(tlfu.check_invariants(&metadata).is_ok());
+        // far over capacity
+        assert_eq!(tlfu.set_protected_capacity(10, 0, &mut metadata), 0);
+        for i in 0..100 {
+            tlfu.access(&format!("key:{}", i), &clock, &mut metadata)
+                .unwrap();
+        }
+        assert_eq!(tlfu.slru.protected_len(), 100);
+        assert_eq!(tlfu.len(), 1000);
+        assert!(tlfu.check_invariants(&metadata).is_ok());
+        // hit moves entry to front, so it is demoted last
+        tlfu.access("key:0", &clock, &mut metadata).unwrap();
+        assert_eq!(tlfu.demote_protected(usize::MAX, &mut metadata), 90);
+        let index = metadata.get("key:0").unwrap();
+        assert_eq!(metadata.data[index as usize].link_id, 3);
+        assert!(tlfu.check_invariants(&metadata).is_ok());
+    }
+
+    #[test]
+    fn test_tlfu_admission_stats() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        for i in 0..100 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        assert_eq!((tlfu.admit_wins, tlfu.admit_losses), (0, 0));
+        // window size is 1, each new key pushes previous window key to contest
+        tlfu.set(key_to_index("cold", &mut metadata), &mut metadata);
+        assert_eq!((tlfu.admit_wins, tlfu.admit_losses), (0, 1));
+        for _ in 0..5 {
+            tlfu.access("hot", &clock, &mut metadata).unwrap();
+        }
+        tlfu.set(key_to_index("hot", &mut metadata), &mut metadata);
+        assert_eq!((tlfu.admit_wins, tlfu.admit_losses), (0, 2));
+        // hot candidate wins against cold victim
+        tlfu.set(key_to_index("next", &mut metadata), &mut metadata);
+        assert_eq!((tlfu.admit_wins, tlfu.admit_losses), (1, 2));
+    }
+
+    #[test]
+    fn test_tlfu_reset_adaptation() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        for i in 0..2000 {
+            let key = format!("key:{}", i % 150);
+            if tlfu.access(&key, &clock, &mut metadata).unwrap().is_none() {
+                tlfu.set(key_to_index(&key, &mut metadata), &mut metadata);
+            }
+        }
+        tlfu.lru_factor = 5;
+        tlfu.step = -3;
+        tlfu.reset_adaptation();
+        assert_eq!(tlfu.lru_factor, 0);
+        assert_eq!(tlfu.step, 1);
+        assert_eq!((tlfu.total, tlfu.hit), (0, 0));
+        assert_eq!(tlfu.hr, 0.0);
+        assert_eq!(tlfu.len(), 100);
+    }
+
+    #[test]
+    fn test_tlfu_no_window() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        tlfu.disable_window();
+        for i in 0..100 {
+            let evicted = tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+            assert!(evicted.is_none());
+        }
+        assert_eq!(tlfu.segment_lens(), (0, 100, 0));
+        tlfu.access("key:0", &clock, &mut metadata).unwrap();
+        assert_eq!(tlfu.segment_lens(), (0, 99, 1));
+        // no admission contest, probation tail is evicted
+        let evicted = tlfu.set(key_to_index("new", &mut metadata), &mut metadata);
+        assert_eq!(evicted, metadata.get("key:1"));
+        let index = metadata.get("new").unwrap();
+        assert_eq!(metadata.data[index as usize].link_id, 2);
+        assert_eq!(tlfu.victim(&mut metadata), metadata.get("key:2"));
+        assert_eq!(tlfu.len(), 100);
+    }
+
+    #[test]
+    fn test_tlfu_with_layout() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        tlfu.set_layout(10, 20, 70, &mut metadata);
+        let clock = Clock::new();
+        assert_eq!(tlfu.capacity(), 100);
+        assert_eq!(tlfu.window_ratio(), 0.1);
+        for i in 0..100 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        assert_eq!(tlfu.segment_lens(), (10, 90, 0));
+        for i in 0..90 {
+            tlfu.access(&format!("key:{}", i), &clock, &mut metadata)
+                .unwrap();
+        }
+        assert_eq!(tlfu.segment_lens(), (10, 20, 70));
+
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        tlfu.set_layout(0, 50, 50, &mut metadata);
+        assert_eq!(tlfu.capacity(), 100);
+        assert_eq!(tlfu.lru.capacity(), 0);
+        assert_eq!(tlfu.window_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_tlfu_sample() {
+        let mut metadata = MetaData::new(1000);
+        let mut tlfu = TinyLfu::new(1000, &mut metadata);
+        tlfu.rng = StdRng::seed_from_u64(0);
+        for i in 0..1000 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        assert!(tlfu.sample(0, &metadata).is_empty());
+        let mut sampled = tlfu.sample(2000, &metadata);
+        assert_eq!(sampled.len(), 1000);
+        sampled.sort();
+        sampled.dedup();
+        assert_eq!(sampled.len(), 1000);
+        // probation front holds recent keys, they are picked more often than old ones
+        let mut recent = 0;
+        let mut old = 0;
+        for _ in 0..100 {
+            for index in tlfu.sample(10, &metadata) {
+                let key = metadata.data[index as usize].key.to_string();
+                let n: usize = key[4..].parse().unwrap();
+                if n >= 900 {
+                    recent += 1;
+                } else if n < 100 {
+                    old += 1;
+                }
+            }
+        }
+        assert!(recent > old * 2);
+    }
+
+    #[test]
+    fn test_tlfu_pinned() {
+        let mut metadata = MetaData::new(10);
+        let mut tlfu = TinyLfu::new(10, &mut metadata);
+        for i in 0..10 {
+            let index = key_to_index(&format!("key:{}", i), &mut metadata);
+            tlfu.set(index, &mut metadata);
+            metadata.data[index as usize].pinned = true;
+        }
+        assert_eq!(tlfu.segment_lens(), (1, 9, 0));
+        // window and probation are all pinned, new entries are rejected
+        for i in 10..20 {
+            let index = key_to_index(&format!("key:{}", i), &mut metadata);
+            assert_eq!(tlfu.set(index, &mut metadata), Some(index));
+            metadata.remove(index);
+        }
+        assert_eq!(tlfu.len(), 10);
+        let index = metadata.get("key:5").unwrap();
+        metadata.data[index as usize].pinned = false;
+        let new = key_to_index("new", &mut metadata);
+        tlfu.admit_ties = true;
+        assert_eq!(tlfu.set(new, &mut metadata), Some(index));
+        assert_eq!(tlfu.len(), 10);
+    }
+
+    #[test]
+    fn test_tlfu_record_misses() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        tlfu.record_misses = false;
+        for _ in 0..5 {
+            assert!(tlfu.access("a", &clock, &mut metadata).unwrap().is_none());
+        }
+        assert_eq!(tlfu.sketch.estimate(tlfu.hasher.hash_one("a")), 0);
+        tlfu.set(key_to_index("a", &mut metadata), &mut metadata);
+        tlfu.access("a", &clock, &mut metadata).unwrap();
+        assert_eq!(tlfu.sketch.estimate(tlfu.hasher.hash_one("a")), 1);
+    }
+
+    #[test]
+    fn test_tlfu_access_expired() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        let index = key_to_index("a", &mut metadata);
+        tlfu.set(index, &mut metadata);
+        tlfu.access("a", &clock, &mut metadata).unwrap();
+        metadata.data[index as usize].expire = clock.now_ns();
+        for _ in 0..10 {
+            assert!(tlfu.access("a", &clock, &mut metadata).unwrap().is_none());
+        }
+        assert_eq!(tlfu.sketch.estimate(tlfu.hasher.hash_one("a")), 1);
+    }
+
+    #[test]
+    fn test_tlfu_admit_trace() {
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(100, &mut metadata);
+        let clock = Clock::new();
+        tlfu.access("a", &clock, &mut metadata).unwrap();
+        assert!(tlfu.admit("a", "b"));
+        assert!(tlfu.admit_trace.is_none());
+        tlfu.admit_trace = Some(Vec::new());
+        assert!(tlfu.admit("a", "b"));
+        assert!(!tlfu.admit("b", "a"));
+        assert_eq!(
+            tlfu.admit_trace.unwrap(),
+            vec![(1, 0, false, true, false), (0, 1, false, false, false)]
+        );
+        // window eviction contests are marked
+        let mut metadata = MetaData::new(100);
+        let mut tlfu = TinyLfu::new(10, &mut metadata);
+        tlfu.admit_trace = Some(Vec::new());
+        for i in 0..11 {
+            tlfu.set(
+                key_to_index(&format!("key:{}", i), &mut metadata),
+                &mut metadata,
+            );
+        }
+        let trace = tlfu.admit_trace.unwrap();
+        assert_eq!(trace.len(), 1);
+        assert!(trace[0].4);
+    }
+
     #[test]
     fn test_tlfu_set_same() {
         let mut metadata = MetaData::new(1000);
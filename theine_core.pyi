@@ -1,34 +1,267 @@
-from typing import Optional, Tuple, Any, Dict, List
+from typing import Optional, Tuple, Any, Callable, Dict, List
 
 class TlfuCore:
-    def __init__(self, size: int): ...
+    def __init__(
+        self,
+        size: int,
+        admit_ties: Optional[bool] = None,
+        early_expire_beta: Optional[float] = None,
+        seed: Optional[int] = None,
+        no_window: Optional[bool] = None,
+        sketch_size: Optional[int] = None,
+        record_misses: Optional[bool] = None,
+        zero_ttl_means_no_cache: Optional[bool] = None,
+        recency_only: Optional[bool] = None,
+        count_accesses: Optional[bool] = None,
+    ): ...
+    @staticmethod
+    def with_layout(
+        window: int, probation: int, protected: int, frozen: Optional[bool] = None
+    ) -> "TlfuCore": ...
+    def set(self, key: str, ttl: int) -> Tuple[int, Optional[int], Optional[str]]:
+        """Return (index, evicted_index, evicted_key). Index 0 means the key was not
+        stored (zero_ttl_means_no_cache with ttl 0), don't write its value."""
+    def set_with_frequency(
+        self, key: str, ttl: int, freq: int
+    ) -> Tuple[int, Optional[int], Optional[str]]: ...
+    def prime_sketch(self, entries: List[Tuple[str, int]]): ...
+    def set_secs(
+        self, key: str, ttl_secs: float
+    ) -> Tuple[int, Optional[int], Optional[str]]: ...
+    def set_millis(
+        self, key: str, ttl_ms: int
+    ) -> Tuple[int, Optional[int], Optional[str]]: ...
+    def try_set(
+        self, key: str, ttl: int
+    ) -> Tuple[Optional[int], Optional[int], Optional[str], bool]: ...
+    def set_drain(self, key: str, ttl: int) -> Tuple[int, List[Tuple[int, str]]]: ...
+    def last_eviction_for(self, inserted_key: str) -> Optional[str]: ...
+    def pin(self, key: str) -> bool: ...
+    def unpin(self, key: str) -> bool: ...
+    def last_set_migrated_to_main(self) -> Optional[str]: ...
+    def set_ns(
+        self, namespace: str, key: str, ttl: int
+    ) -> Tuple[int, Optional[int], Optional[str]]: ...
+    def access_ns(self, namespace: str, key: str) -> Optional[int]: ...
+    def remove_ns(self, namespace: str, key: str) -> Optional[int]: ...
+    def remove_prefix(self, prefix: str) -> List[int]: ...
+    def remove(self, key: str) -> Optional[int]: ...
+    def access_count(self, key: str) -> Optional[int]: ...
+    def key_at(self, index: int) -> Optional[str]: ...
+    def remove_by_index(self, index: int) -> Optional[str]: ...
+    def access(self, key: str) -> Optional[int]: ...
+    def get_or_compute_flag(
+        self, key: str, ttl: int
+    ) -> Tuple[Optional[int], bool, Optional[int], Optional[str]]: ...
+    def release_compute(self, key: str) -> bool: ...
+    def reserve(
+        self, key: str, ttl: int
+    ) -> Tuple[int, Optional[int], Optional[str]]: ...
+    def commit(self, key: str) -> bool: ...
+    def cancel(self, key: str) -> Optional[int]: ...
+    def access_allow_stale(self, key: str) -> Optional[Tuple[int, bool]]: ...
+    def access_or_reap(self, key: str) -> Tuple[Optional[int], Optional[int]]: ...
+    def access_with_ttl(self, key: str) -> Optional[Tuple[int, int]]: ...
+    def expire_at(self, key: str, ttl: int) -> bool: ...
+    def expire_at_many(self, items: List[Tuple[str, int]]) -> int: ...
+    def decay_if_idle(self, idle_ns: int) -> bool: ...
+    def advance(
+        self,
+        cache: List,
+        sentinel: Any,
+        kh: Dict,
+        hk: Dict,
+        max_evictions: Optional[int] = None,
+    ) -> List[Tuple[int, str]]: ...
+    def advance_level(
+        self, level: int, cache: List, sentinel: Any, kh: Dict, hk: Dict
+    ) -> List[Tuple[int, str]]: ...
+    def advance_keys(self, max_evictions: Optional[int] = None) -> List[str]: ...
+    def advance_steps(self, timestamps: List[int]) -> List[List[str]]: ...
+    def clear(self, preserve_sketch: Optional[bool] = None): ...
+    def len(self) -> int: ...
+    def capacity(self) -> int: ...
+    def fill_ratio(self) -> float: ...
+    def dump_order(self) -> List[List[str]]: ...
+    def span_boundaries(self) -> List[int]: ...
+    def current_nanos(self) -> int: ...
+    def nanos_until_next_tick(self) -> int: ...
+    def live_len(self) -> int: ...
+    def peek_expired(self) -> List[str]: ...
+    def expiring_within(self, window_ns: int) -> List[str]: ...
+    def scheduled_count(self) -> int: ...
+    def set_random_admit_rate(self, one_in: int):
+        """Admit a losing candidate with sketch frequency of at least 6 with
+        probability 1 / one_in, against hash flooding. Default 0 (disabled)."""
+    def random_admit_rate(self) -> int: ...
+    def admission_stats(self) -> Tuple[int, int]: ...
+    def reset_admission_stats(self): ...
+    def total_inserts(self) -> int: ...
+    def reset_total_inserts(self): ...
+    def enable_admit_trace(self, enabled: bool): ...
+    def admit_trace(self) -> List[Tuple[int, int, bool, bool, bool]]: ...
+    def enable_reap_on_access(self, enabled: bool): ...
+    def take_reaped(self) -> List[Tuple[int, str]]: ...
+    def enable_trace(self, capacity: int): ...
+    def drain_trace(self) -> List[Tuple[str, int]]: ...
+    def overflow_count(self) -> int: ...
+    def sketch_resets(self) -> int: ...
+    def last_reset_additions(self) -> int: ...
+    def window_ratio(self) -> float: ...
+    def ewma_hit_ratio(self) -> float: ...
+    def should_shadow(self, key: str, sample_rate: float) -> bool: ...
+    def set_ewma_alpha(self, alpha: float): ...
+    def set_reset_floor(self, enabled: bool): ...
+    def set_admission_prior(self, prior: Optional["BloomFilter"]): ...
+    def reset_adaptation(self): ...
+    def set_protected_capacity(
+        self, capacity: int, max_demotions: Optional[int] = None
+    ) -> int: ...
+    def demote_protected(self, max: int) -> int: ...
+    def pending_demotions(self) -> int: ...
+    def demotion_count(self) -> int: ...
+    def shrink_to_fit(self) -> Tuple[int, List[Tuple[int, int]]]:
+        """Compact live entries into the lowest slots and release free slots. Return
+        (released, moves): apply moves in order as cache[to] = cache[from], then drop
+        the last released slots. Indexes from before the call are no longer valid."""
+    def defragment(self, cache: List, sentinel: Any) -> int: ...
+    def set_eviction_callback(
+        self, callback: Optional[Callable[[str, str], Any]]
+    ): ...
+    def snapshot(self) -> TlfuSnapshot: ...
+    def age_extremes(self) -> Optional[Tuple[int, int]]: ...
+    def drain(self) -> List[Tuple[str, int, int]]: ...
+    def export_entries(self) -> List[Tuple[str, int, int]]: ...
+    def resident_frequency_mean(self) -> float: ...
+    def is_saturated(self, key: str) -> bool: ...
+    def sample_keys(self, k: int) -> List[str]: ...
+    def resize_sketch(self, size: int): ...
+    def saturation_ratio(self) -> float: ...
+    def key_hash(self, key: str) -> int: ...
+    def keys_collide(self, a: str, b: str) -> bool: ...
+    def check_invariants(self): ...
+    def segment_of(self, key: str) -> Optional[int]: ...
+
+class ClockProCore:
+    def __init__(
+        self, size: int, min_cold: Optional[int] = None, max_cold: Optional[int] = None
+    ): ...
+    def set(self, key: str, ttl: int) -> ClockProSetResult: ...
+    def remove(self, key: str) -> Optional[int]: ...
+    def access(self, key: str) -> Optional[int]: ...
+    def advance(
+        self,
+        cache: List,
+        sentinel: Any,
+        kh: Dict,
+        hk: Dict,
+        max_evictions: Optional[int] = None,
+    ) -> List[Tuple[int, str]]: ...
+    def clear(self): ...
+    def len(self) -> int: ...
+    def capacity(self) -> int: ...
+    def fill_ratio(self) -> float: ...
+    def pages(self) -> Tuple[List[str], List[str], List[str]]: ...
+    def dump_order(self) -> List[List[str]]: ...
+    def span_boundaries(self) -> List[int]: ...
+    def current_nanos(self) -> int: ...
+    def nanos_until_next_tick(self) -> int: ...
+    def set_capacity(self, size: int) -> List[Tuple[int, str]]: ...
+    def dump(
+        self,
+    ) -> Tuple[
+        List[Tuple[str, int, bool, int]],
+        Tuple[Optional[str], Optional[str], Optional[str]],
+        int,
+    ]: ...
+    def load(
+        self,
+        state: Tuple[
+            List[Tuple[str, int, bool, int]],
+            Tuple[Optional[str], Optional[str], Optional[str]],
+            int,
+        ],
+    ): ...
+
+class ClockProSetResult:
+    index: int
+    demoted_index: Optional[int]
+    removed_index: Optional[int]
+    removed_key: Optional[str]
+
+class TlfuSnapshot:
+    len: int
+    hits: int
+    misses: int
+    window_len: int
+    probation_len: int
+    protected_len: int
+    scheduled_count: int
+    lru_factor: int
+
+class LruCore:
+    def __init__(self, size: int, promote_on_set: Optional[bool] = None): ...
     def set(self, key: str, ttl: int) -> Tuple[int, Optional[int], Optional[str]]: ...
     def remove(self, key: str) -> Optional[int]: ...
     def access(self, key: str) -> Optional[int]: ...
-    def advance(self, cache: List, sentinel: Any, kh: Dict, hk: Dict): ...
+    def advance(
+        self,
+        cache: List,
+        sentinel: Any,
+        kh: Dict,
+        hk: Dict,
+        max_evictions: Optional[int] = None,
+    ) -> List[str]: ...
     def clear(self): ...
     def len(self) -> int: ...
+    def capacity(self) -> int: ...
+    def fill_ratio(self) -> float: ...
+    def dump_order(self) -> List[List[str]]: ...
+    def span_boundaries(self) -> List[int]: ...
+    def current_nanos(self) -> int: ...
+    def nanos_until_next_tick(self) -> int: ...
+    def set_capacity(self, size: int) -> List[Tuple[int, str]]: ...
 
-class ClockProCore:
+class FifoCore:
     def __init__(self, size: int): ...
-    def set(
-        self, key: str, ttl: int
-    ) -> Tuple[int, Optional[int], Optional[int], Optional[str]]: ...
+    def set(self, key: str, ttl: int) -> Tuple[int, Optional[int], Optional[str]]: ...
     def remove(self, key: str) -> Optional[int]: ...
     def access(self, key: str) -> Optional[int]: ...
-    def advance(self, cache: List, sentinel: Any, kh: Dict, hk: Dict): ...
+    def advance(
+        self,
+        cache: List,
+        sentinel: Any,
+        kh: Dict,
+        hk: Dict,
+        max_evictions: Optional[int] = None,
+    ) -> List[str]: ...
     def clear(self): ...
     def len(self) -> int: ...
 
-class LruCore:
+class LfuCore:
     def __init__(self, size: int): ...
     def set(self, key: str, ttl: int) -> Tuple[int, Optional[int], Optional[str]]: ...
     def remove(self, key: str) -> Optional[int]: ...
     def access(self, key: str) -> Optional[int]: ...
-    def advance(self, cache: List, sentinel: Any, kh: Dict, hk: Dict): ...
+    def advance(
+        self,
+        cache: List,
+        sentinel: Any,
+        kh: Dict,
+        hk: Dict,
+        max_evictions: Optional[int] = None,
+    ) -> List[str]: ...
     def clear(self): ...
     def len(self) -> int: ...
 
 class BloomFilter:
+    def __init__(self, insertions: int, fpp: float): ...
     def put(self, key: str): ...
     def contains(self, key: str) -> bool: ...
+    def put_hash(self, h: int): ...
+    def contains_hash(self, h: int) -> bool: ...
+    def additions(self) -> int: ...
+    def insertions(self) -> int: ...
+    def clear(self): ...
+    def remaining_capacity(self) -> int: ...
+    def is_full(self) -> bool: ...